@@ -0,0 +1,14 @@
+#![no_main]
+
+use edf_to_csv::{read_header, read_record_samples};
+use libfuzzer_sys::fuzz_target;
+
+// Arbitrary bytes must make the header parser return an error, never panic.
+// When a header does parse, reading the first record must be just as safe.
+fuzz_target!(|data: &[u8]| {
+    let mut reader: &[u8] = data;
+    if let Ok(header) = read_header(&mut reader) {
+        let record_size: usize = header.signals.iter().map(|s| s.num_samples).sum();
+        let _ = read_record_samples(&mut reader, record_size);
+    }
+});
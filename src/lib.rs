@@ -0,0 +1,1493 @@
+extern crate byteorder;
+extern crate chrono;
+extern crate csv;
+extern crate serde;
+extern  crate thiserror;
+extern crate zip;
+
+use std::borrow::Cow;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::thread;
+use std::time::Duration;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt, LittleEndian};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::Serialize;
+use thiserror::Error;
+
+
+const MAX_PREALLOCATED_SAMPLES: usize = 1 << 20;
+
+/// Most signals a header can declare: the field is 4 ASCII characters, so
+/// formats with more signals (e.g. HD-EDF variants) can't be read.
+pub const MAX_SIGNALS: usize = 9999;
+
+/// The timestamp format of [`ConversionOptions::default`], e.g.
+/// "2023-01-15T08:30:00.250".
+pub const DEFAULT_DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f";
+
+
+/// Calibration of a signal: digital sample codes in
+/// `digital_min..=digital_max` map linearly onto `physical_min..=physical_max`.
+#[derive(Debug, Serialize)]
+pub struct Bounds {
+    pub digital_min: f64,
+    pub digital_max: f64,
+    pub physical_min: f64,
+    pub physical_max: f64
+}
+impl Bounds {
+    /// Converts a digital sample to its physical value. `i16::MIN` marks a
+    /// missing sample and gives `None`.
+    pub fn scale(&self, &value: &i16) -> Option<f64> {
+        if value == i16::MIN {
+            return None;
+        }
+        let value: f64 = value as f64;
+        let digital_range: f64 = self.digital_max - self.digital_min;
+        let physical_range: f64 = self.physical_max - self.physical_min;
+
+        Some(((value - self.digital_min) * physical_range / digital_range) + self.physical_min)
+    }
+
+    /// The inverse of [`Bounds::scale`]: rounds a physical value to the nearest
+    /// digital code, clamped to the digital range. `None` gives `i16::MIN`.
+    pub fn unscale(&self, value: Option<f64>) -> i16 {
+        let Some(value) = value else {
+            return i16::MIN;
+        };
+        let digital_range: f64 = self.digital_max - self.digital_min;
+        let physical_range: f64 = self.physical_max - self.physical_min;
+        let digital: f64 = ((value - self.physical_min) * digital_range / physical_range) + self.digital_min;
+        digital.round().clamp(self.digital_min, self.digital_max) as i16
+    }
+
+    /// Whether `value` sits exactly on the declared digital minimum or maximum,
+    /// which many clinical systems use to mark off-scale low and high samples.
+    pub fn is_off_scale(&self, value: i16) -> bool {
+        let value: f64 = value as f64;
+        value == self.digital_min || value == self.digital_max
+    }
+}
+
+
+#[derive(Debug, Serialize)]
+pub struct Signal {
+    pub label: String,
+    pub dimension: String,
+    pub bounds: Bounds,
+    pub num_samples: usize
+}
+impl Signal {
+    /// EDF+ stores annotations in signals with this label; their samples
+    /// are raw TAL bytes rather than digital values.
+    pub fn is_annotation(&self) -> bool {
+        self.label == "EDF Annotations"
+    }
+
+    pub fn sample_rate(&self, record_duration: f64) -> f64 {
+        self.num_samples as f64 / record_duration
+    }
+
+    /// The exact time between two samples in milliseconds, which needn't be
+    /// a whole number (e.g. 3.90625 ms at 256 Hz).
+    pub fn sample_interval_ms(&self, record_duration: f64) -> f64 {
+        1000.0 * record_duration / self.num_samples as f64
+    }
+
+    /// Scales one digital sample with this signal's bounds.
+    pub fn scale_sample(&self, value: i16) -> Option<f64> {
+        self.bounds.scale(&value)
+    }
+
+    /// Scales a run of digital samples, e.g. this signal's part of a record.
+    pub fn scale_samples(&self, values: &[i16]) -> Vec<Option<f64>> {
+        values.iter().map(|value| self.bounds.scale(value)).collect()
+    }
+}
+
+
+/// One EDF+ time-stamped annotation list: "+onset[\x15duration]\x14text\x14...\x00".
+/// The first TAL of every record only keeps time and has no texts.
+#[derive(Debug, Serialize)]
+pub struct Tal {
+    pub onset: f64,
+    pub duration: Option<f64>,
+    pub texts: Vec<String>
+}
+
+
+/// The sleep staging and respiratory "standard texts" EDF+ defines for
+/// annotations, including the AASM stages N1..N3 of its later revision.
+pub const STANDARD_TEXTS: [&str; 25] = [
+    "Sleep stage W",
+    "Sleep stage 1",
+    "Sleep stage 2",
+    "Sleep stage 3",
+    "Sleep stage 4",
+    "Sleep stage R",
+    "Sleep stage ?",
+    "Sleep stage N",
+    "Sleep stage N1",
+    "Sleep stage N2",
+    "Sleep stage N3",
+    "Movement time",
+    "Arousal",
+    "Central apnea",
+    "Obstructive apnea",
+    "Mixed apnea",
+    "Central hypopnea",
+    "Obstructive hypopnea",
+    "Mixed hypopnea",
+    "Hypopnea",
+    "Periodic respiration",
+    "Respiratory effort related arousal",
+    "Desaturation",
+    "Limb movement",
+    "Periodic limb movement"
+];
+
+
+/// Whether an annotation text is one of the EDF+ standard texts. They are
+/// matched exactly, as the specification spells them.
+pub fn is_standard_text(text: &str) -> bool {
+    STANDARD_TEXTS.contains(&text)
+}
+
+
+/// Parses the TALs in one record's part of an annotation signal.
+/// Malformed TALs are skipped, trailing zero padding is ignored.
+pub fn parse_tals(bytes: &[u8]) -> Vec<Tal> {
+    let mut tals: Vec<Tal> = Vec::new();
+    for tal in bytes.split(|&b| b == 0).filter(|tal| !tal.is_empty()) {
+        let mut parts = tal.split(|&b| b == 0x14);
+        let time: &[u8] = parts.next().unwrap_or_default();
+        let mut time_parts = time.split(|&b| b == 0x15);
+        let onset: Option<f64> = time_parts.next()
+            .and_then(|onset| std::str::from_utf8(onset).ok())
+            .filter(|onset| onset.starts_with('+') || onset.starts_with('-'))
+            .and_then(|onset| onset.parse::<f64>().ok());
+        let Some(onset) = onset else { continue };
+        let duration: Option<f64> = time_parts.next()
+            .and_then(|duration| std::str::from_utf8(duration).ok())
+            .and_then(|duration| duration.parse::<f64>().ok());
+        let texts: Vec<String> = parts
+            .filter(|text| !text.is_empty())
+            .map(|text| String::from_utf8_lossy(text).into_owned())
+            .collect();
+        tals.push(Tal {onset, duration, texts});
+    }
+    tals
+}
+
+
+/// An annotation signal's samples back as the bytes they were read from.
+pub fn annotation_bytes(raw_values: &[i16], endian: Endian) -> Vec<u8> {
+    match endian {
+        Endian::Little => raw_values.iter().flat_map(|value| value.to_le_bytes()).collect(),
+        Endian::Big => raw_values.iter().flat_map(|value| value.to_be_bytes()).collect()
+    }
+}
+
+
+/// Each data signal of a record with its part of the record's samples;
+/// annotation signals are left out.
+pub fn data_samples<'a>(signals: &'a [Signal], values: &'a [i16]) -> impl Iterator<Item = (&'a Signal, &'a [i16])> {
+    let mut offset: usize = 0;
+    signals
+        .iter()
+        .map(move |signal| {
+            offset += signal.num_samples;
+            (signal, &values[offset - signal.num_samples..offset])
+        })
+        .filter(|(signal, _)| !signal.is_annotation())
+}
+
+
+/// Onset of a record's time-keeping TAL, the first one in its first
+/// annotation signal, or None for plain EDF.
+pub fn time_keeping_onset(signals: &[Signal], values: &[i16], endian: Endian) -> Option<f64> {
+    let mut offset: usize = 0;
+    for signal in signals {
+        if signal.is_annotation() {
+            let bytes: Vec<u8> = annotation_bytes(&values[offset..offset + signal.num_samples], endian);
+            return parse_tals(&bytes).first().map(|tal| tal.onset);
+        }
+        offset += signal.num_samples;
+    }
+    None
+}
+
+
+/// EDF+ keeps sub-second start times out of the header's hh.mm.ss: the
+/// time-keeping TAL that leads a record's first annotation signal gives the
+/// record's onset from that whole second. Any fraction of a second past where
+/// `record` nominally starts is the amount to shift the timeline by. TAL
+/// onsets stay relative to the header's start, so events aren't shifted.
+pub fn fractional_start(signals: &[Signal], values: &[i16], record: usize, record_duration: f64, endian: Endian) -> chrono::Duration {
+    if let Some(onset) = time_keeping_onset(signals, values, endian) {
+        let fraction: f64 = onset - record as f64 * record_duration;
+        if fraction > 0.0 && fraction < 1.0 {
+            return chrono::Duration::nanoseconds((fraction * 1e9).round() as i64);
+        }
+    }
+    chrono::Duration::zero()
+}
+
+
+/// Subfields of the EDF+ local patient identification:
+/// "code sex birthdate name additional...", with the birthdate as
+/// dd-MMM-yyyy. Unknown subfields are written as "X".
+#[derive(Debug, Serialize)]
+pub struct PatientInfo {
+    pub code: String,
+    pub sex: String,
+    pub birthdate: String,
+    pub name: String,
+    pub additional: String
+}
+impl PatientInfo {
+    /// Plain EDF leaves the field free-form, so it is only split up when it
+    /// has the four EDF+ subfields with "M", "F" or "X" as the sex. A valid
+    /// birthdate is normalized to upper case, e.g. "02-MAY-1951".
+    pub fn parse(field: &str) -> Option<PatientInfo> {
+        let parts: Vec<&str> = field.split_whitespace().collect();
+        if parts.len() < 4 || !["M", "F", "X"].contains(&parts[1]) {
+            return None;
+        }
+        let birthdate: String = match NaiveDate::parse_from_str(parts[2], "%d-%b-%Y") {
+            Ok(date) => format_edf_plus_date(date),
+            Err(_) => parts[2].to_string()
+        };
+        Some(PatientInfo {
+            code: parts[0].to_string(),
+            sex: parts[1].to_string(),
+            birthdate,
+            name: parts[3].to_string(),
+            additional: parts[4..].join(" ")
+        })
+    }
+
+    pub fn birthdate(&self) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(&self.birthdate, "%d-%b-%Y").ok()
+    }
+}
+
+
+// Dates in the EDF+ identification fields, e.g. "02-MAY-1951".
+fn format_edf_plus_date(date: NaiveDate) -> String {
+    date.format("%d-%b-%Y").to_string().to_uppercase()
+}
+
+
+/// Subfields of the EDF+ local recording identification:
+/// "Startdate dd-MMM-yyyy admincode technician equipment additional...".
+/// Unknown subfields are written as "X".
+#[derive(Debug, Serialize)]
+pub struct RecordingInfo {
+    pub startdate: String,
+    pub admin_code: String,
+    pub technician: String,
+    pub equipment: String,
+    pub additional: String
+}
+impl RecordingInfo {
+    pub fn parse(field: &str) -> Option<RecordingInfo> {
+        let mut parts = field.split_whitespace();
+        if parts.next() != Some("Startdate") {
+            return None;
+        }
+        let mut next = || parts.next().unwrap_or("X").to_string();
+        let startdate: String = next();
+        let admin_code: String = next();
+        let technician: String = next();
+        let equipment: String = next();
+        let additional: Vec<&str> = parts.collect();
+        Some(RecordingInfo {
+            startdate,
+            admin_code,
+            technician,
+            equipment,
+            additional: additional.join(" ")
+        })
+    }
+
+    pub fn start_date(&self) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(&self.startdate, "%d-%b-%Y").ok()
+    }
+}
+
+
+/// Everything in the header record, as returned by [`read_header`]. Reading
+/// it stops at the first data record, so files can be inspected cheaply
+/// without converting them.
+#[derive(Debug)]
+pub struct EdfHeader {
+    pub start: NaiveDateTime,
+    pub patient_info: Option<PatientInfo>,
+    pub recording_info: Option<RecordingInfo>,
+    pub num_records: usize,
+    pub record_duration: f64,
+    pub signals: Vec<Signal>,
+    /// Byte offset of the first data record.
+    pub data_offset: u64,
+    /// Non-fatal problems noticed while parsing.
+    pub warnings: Vec<String>
+}
+impl EdfHeader {
+    /// Number of signals, annotation signals included.
+    pub fn num_signals(&self) -> usize {
+        self.signals.len()
+    }
+}
+
+
+#[derive(Error, Debug, Serialize)]
+#[serde(tag = "error_kind", content = "detail")]
+pub enum EdfError {
+    #[error("Can't perform csv operation.")]
+    Csv(String),
+    #[error("Can't parse value to float.")]
+    ParseFloat(String),
+    #[error("Can't parse value to int.")]
+    ParseInt(String),
+    #[error("Can't perform I/O operation.")]
+    Io(String),
+    #[error("Can't parse datetime.")]
+    Datetime(String),
+    #[error("Not all signals have the same number of samples per record: {0}")]
+    MismatchedSignals(String),
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("Signals don't match the merged output!")]
+    SchemaMismatch(String),
+    #[error("CSV doesn't match the EDF file: {0}")]
+    VerificationFailed(String),
+    #[error("Can't read ZIP archive.")]
+    Zip(String),
+    #[error("Conversion timed out.")]
+    Timeout(String),
+    #[error("Can't write EDF file: {0}")]
+    EdfWrite(String),
+    #[error("Signals don't match the expected schema: {0}")]
+    UnexpectedSchema(String),
+    #[error("Can't write to the SQLite database: {0}")]
+    Sqlite(String),
+    #[error("Can't download the file: {0}")]
+    Http(String),
+    #[error("Timestamps drift from the recording's duration: {0}")]
+    TimestampDrift(String),
+    #[error("Scaled values aren't finite: {0}")]
+    NonFinite(String),
+    #[error("Invalid header: {0}")]
+    InvalidHeader(String),
+    #[error("Rows share a timestamp: {0}")]
+    DuplicateTimestamp(String),
+    #[error("Can't convert to the target unit: {0}")]
+    UnitConversion(String)
+}
+
+
+impl std::convert::From<csv::Error> for EdfError {
+    fn from(err: csv::Error) -> Self {
+        EdfError::Csv(err.to_string())
+    }
+}
+
+impl std::convert::From<zip::result::ZipError> for EdfError {
+    fn from(err: zip::result::ZipError) -> Self {
+        EdfError::Zip(err.to_string())
+    }
+}
+
+impl std::convert::From<std::io::Error> for EdfError {
+    fn from(err: std::io::Error) -> Self {
+        EdfError::Io(err.to_string())
+    }
+}
+
+impl std::convert::From<std::num::ParseFloatError> for EdfError {
+    fn from(err: std::num::ParseFloatError) -> Self {
+        EdfError::ParseFloat(err.to_string())
+    }
+}
+
+impl std::convert::From<std::num::ParseIntError> for EdfError {
+    fn from(err: std::num::ParseIntError) -> Self {
+        EdfError::ParseInt(err.to_string())
+    }
+}
+
+
+/// Retries reads that fail with a transient error, waiting twice as long
+/// before each attempt. End of file, permission problems and malformed data
+/// are returned straight away.
+pub struct RetryReader<R> {
+    inner: R,
+    retries: u32
+}
+impl<R: Read> RetryReader<R> {
+    pub fn new(inner: R, retries: u32) -> Self {
+        RetryReader { inner, retries }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+}
+impl<R: Read> Read for RetryReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut backoff: Duration = Duration::from_millis(50);
+        let mut attempt: u32 = 0;
+        loop {
+            match self.inner.read(buf) {
+                Err(e) if attempt < self.retries && is_transient(&e) => {
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                },
+                result => return result
+            }
+        }
+    }
+}
+
+
+/// Seeks aren't retried; they don't wait on the device the way reads do.
+impl<R: Seek> Seek for RetryReader<R> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(position)
+    }
+}
+
+
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+    )
+}
+
+
+// Header fields that aren't needed are read and dropped rather than seeked
+// over, so the header parser works on any reader.
+fn skip<R: Read>(reader: &mut R, bytes: u64) -> Result<(), EdfError> {
+    io::copy(&mut reader.by_ref().take(bytes), &mut io::sink())?;
+    Ok(())
+}
+
+
+fn get_patient_field<R: Read>(reader: &mut R) -> Result<String, EdfError> {
+    let skip_bytes: u64 = 8;
+    skip(reader, skip_bytes)?;
+
+    let mut patient: String = String::with_capacity(80);
+    reader.by_ref().take(80).read_to_string(&mut patient)?;
+    Ok(patient.trim().to_string())
+}
+
+
+fn get_recording_field<R: Read>(reader: &mut R) -> Result<String, EdfError> {
+    let mut recording: String = String::with_capacity(80);
+    reader.by_ref().take(80).read_to_string(&mut recording)?;
+    Ok(recording.trim().to_string())
+}
+
+
+// The whole field is read before parsing, so a bad date leaves the reader at
+// the start time and the caller can fall back to a default.
+fn get_start_date_field<R: Read>(reader: &mut R) -> Result<String, EdfError> {
+    let mut date_string = String::with_capacity(8);
+    reader.by_ref().take(8).read_to_string(&mut date_string)?;
+    Ok(date_string)
+}
+
+
+fn parse_start_date(date_string: &str) -> Result<NaiveDate, EdfError> {
+    let day_string: &str = date_string.get(0..2).unwrap_or_default();
+    let day: u32 = day_string.parse()?;
+    let month_string: &str = date_string.get(3..5).unwrap_or_default();
+    let month: u32 = month_string.parse()?;
+    let year_string: &str = date_string.get(6..8).unwrap_or_default();
+    // The spec's clipping date: two-digit years from 85 on are in the 1900s.
+    let short_year: i32 = year_string.parse()?;
+    let year: i32 = if short_year >= 85 { 1900 + short_year } else { 2000 + short_year };
+
+    match NaiveDate::from_ymd_opt(year, month, day) {
+        Some(date) => Ok(date),
+        None => Err(EdfError::Datetime(format!("{}.{}.{} is not a valid date", day_string, month_string, year_string)))
+    }
+}
+
+
+fn get_start_time<R: Read>(reader: &mut R) -> Result<NaiveTime, EdfError> {
+    let mut hour_string = String::with_capacity(2);
+    reader.by_ref().take(2).read_to_string(&mut hour_string)?;
+    let hour: u32 = hour_string.parse()?;
+
+    skip(reader, 1)?;
+
+    let mut minute_string = String::with_capacity(2);
+    reader.by_ref().take(2).read_to_string(&mut minute_string)?;
+    let minute: u32 = minute_string.parse()?;
+
+    skip(reader, 1)?;
+
+    let mut second_string = String::with_capacity(2);
+    reader.by_ref().take(2).read_to_string(&mut second_string)?;
+    let second: u32 = second_string.parse()?;
+
+    match NaiveTime::from_hms_opt(hour, minute, second) {
+        Some(time) => Ok(time),
+        None => Err(EdfError::Datetime(format!("{}.{}.{} is not a valid time", hour_string, minute_string, second_string)))
+    }
+
+}
+
+
+// Some exporters pad numeric fields with NULs or CR/LF instead of spaces, so
+// control characters are dropped along with the surrounding whitespace.
+fn numeric_field(field: &str) -> String {
+    field.chars().filter(|c| !c.is_control()).collect::<String>().trim().to_string()
+}
+
+
+// The declared size of the whole header, or None if it isn't a number.
+fn get_header_bytes<R: Read>(reader: &mut R) -> Result<Option<u64>, EdfError> {
+    let mut header_bytes: String = String::with_capacity(8);
+    reader.by_ref().take(8).read_to_string(&mut header_bytes)?;
+    Ok(numeric_field(&header_bytes).parse().ok())
+}
+
+
+fn get_num_records<R: Read>(reader: &mut R) -> Result<usize , EdfError> {
+    let skip_bytes: u64 = 44;
+    skip(reader, skip_bytes)?;
+
+    let mut num_records: String = String::with_capacity(8);
+    reader.by_ref().take(8).read_to_string(&mut num_records)?;
+    Ok(numeric_field(&num_records).parse()?)
+}
+
+
+fn get_record_duration<R: Read>(reader: &mut R) -> Result<f64 , EdfError> {
+    let mut record_duration: String = String::with_capacity(8);
+    reader.by_ref().take(8).read_to_string(&mut record_duration)?;
+    Ok(numeric_field(&record_duration).parse()?)
+}
+
+
+// The count sizes the signal header reads that follow, so anything outside
+// 1..=MAX_SIGNALS is rejected with the field's contents rather than read on.
+fn get_num_signals<R: Read>(reader: &mut R) -> Result<usize , EdfError> {
+    let mut num_signals: String = String::with_capacity(4);
+    reader.by_ref().take(4).read_to_string(&mut num_signals)?;
+    let num_signals: String = numeric_field(&num_signals);
+    match num_signals.parse::<usize>() {
+        Ok(count) if (1..=MAX_SIGNALS).contains(&count) => Ok(count),
+        _ => Err(EdfError::InvalidHeader(format!("the number of signals '{}' isn't between 1 and {}", num_signals, MAX_SIGNALS)))
+    }
+}
+
+
+// The signal headers are stored field by field: all labels, then all
+// transducer types, and so on, so the whole block is read at once and each
+// field sliced out of it. Transducer type, prefiltering and the reserved
+// field aren't used.
+fn get_signals<R: Read>(reader: &mut R, num_signals: usize) -> Result<Vec<Signal>, EdfError> {
+    let header_signal_bytes: [usize; 10] = [16, 80, 8, 8, 8, 8, 8, 80, 8, 32];
+    let skip_indices: [usize; 3] = [1, 7, 9];
+
+    let mut block: Vec<u8> = vec![0; num_signals * 256];
+    reader.read_exact(&mut block)?;
+
+    let mut signals_vec: Vec<Vec<&str>> = vec![Vec::with_capacity(7); num_signals];
+    let mut offset: usize = 0;
+    for (i, &bytes) in header_signal_bytes.iter().enumerate() {
+        if !skip_indices.contains(&i) {
+            for (n, s) in signals_vec.iter_mut().enumerate() {
+                let field: &[u8] = &block[offset + n * bytes..offset + (n + 1) * bytes];
+                let field: &str = std::str::from_utf8(field).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+                s.push(field.trim());
+            }
+        }
+        offset += num_signals * bytes;
+    }
+
+
+    let mut signals: Vec<Signal> = Vec::with_capacity(num_signals);
+    for s in signals_vec {
+        let num_samples: usize = numeric_field(s[6]).parse()?;
+        signals.push(Signal {
+            bounds: Bounds {
+                physical_min: parse_bound(s[0], "physical minimum", s[2])?,
+                physical_max: parse_bound(s[0], "physical maximum", s[3])?,
+                digital_min: parse_bound(s[0], "digital minimum", s[4])?,
+                digital_max: parse_bound(s[0], "digital maximum", s[5])?
+            },
+            label: s[0].to_string(),
+            dimension: s[1].to_string(),
+            num_samples
+        })
+    }
+
+    Ok(signals)
+}
+
+
+// Vendors pad the bound fields on either side with spaces, tabs or NULs, or
+// add a `+`, so surrounding whitespace and control characters are trimmed
+// before parsing; anything inside the number stays and has to parse. A single
+// comma is taken as the decimal separator when there's no point, unless three
+// digits follow it, which reads as a thousands separator ("1,000") and is
+// rejected as ambiguous.
+fn parse_bound(label: &str, field: &str, value: &str) -> Result<f64, EdfError> {
+    let invalid = || EdfError::InvalidHeader(format!("signal '{}' has {} '{}', which isn't a number", label, field, value));
+    let trimmed: &str = value.trim_matches(|c: char| c.is_whitespace() || c.is_control());
+    let number: Cow<str> = match trimmed.split_once(',') {
+        Some((whole, fraction)) => {
+            let fraction_digits: usize = fraction.chars().take_while(char::is_ascii_digit).count();
+            if trimmed.contains('.') || fraction.contains(',') || fraction_digits == 3 {
+                return Err(invalid());
+            }
+            Cow::Owned(format!("{}.{}", whole, fraction))
+        },
+        None => Cow::Borrowed(trimmed)
+    };
+    number.parse().map_err(|_| invalid())
+}
+
+
+// `get_signals` assumes the field order from the EDF spec. Bounds that come out
+// inverted or degenerate are the most visible symptom of a file that uses a
+// different order, so they are reported rather than silently scaled.
+fn check_signal_bounds(signals: &[Signal]) -> Vec<String> {
+    let mut warnings: Vec<String> = Vec::new();
+    for signal in signals {
+        let bounds: &Bounds = &signal.bounds;
+        if bounds.physical_min > bounds.physical_max {
+            warnings.push(format!(
+                "signal '{}' has physical minimum {} above physical maximum {}; the header fields may be in a non-standard order",
+                signal.label, bounds.physical_min, bounds.physical_max
+            ));
+        }
+        if bounds.digital_min >= bounds.digital_max {
+            warnings.push(format!(
+                "signal '{}' has digital minimum {} not below digital maximum {}; the header fields may be in a non-standard order",
+                signal.label, bounds.digital_min, bounds.digital_max
+            ));
+        }
+    }
+    warnings
+}
+
+
+/// Lists the ordinary signals whose physical dimension is blank, which
+/// leaves their values without a unit.
+pub fn check_missing_units(signals: &[Signal]) -> Vec<String> {
+    signals
+        .iter()
+        .filter(|signal| !signal.is_annotation() && signal.dimension.is_empty())
+        .map(|signal| format!("signal '{}' has no physical dimension; its unit is unknown", signal.label))
+        .collect()
+}
+
+
+/// Heuristic check for voltage signals whose dimension likely has the wrong
+/// unit prefix, e.g. a physical maximum of 10000 in "V" that was meant as
+/// "uV". Only flags ranges far outside anything a body or an amplifier produces.
+pub fn check_unit_prefixes(signals: &[Signal]) -> Vec<String> {
+    let mut warnings: Vec<String> = Vec::new();
+    for signal in signals {
+        let prefix: f64 = match signal.dimension.as_str() {
+            "nV" => 1e-9,
+            "uV" | "µV" => 1e-6,
+            "mV" => 1e-3,
+            "V" => 1.0,
+            "kV" => 1e3,
+            _ => continue
+        };
+        let bounds: &Bounds = &signal.bounds;
+        let magnitude: f64 = bounds.physical_min.abs().max(bounds.physical_max.abs());
+        let volts: f64 = magnitude * prefix;
+        if magnitude > 0.0 && !(1e-8..=100.0).contains(&volts) {
+            warnings.push(format!(
+                "signal '{}' spans up to {} {}, an implausible voltage; the dimension may have the wrong unit prefix",
+                signal.label, magnitude, signal.dimension
+            ));
+        }
+    }
+    warnings
+}
+
+
+/// Expresses `unit` as `(quantity, scale, offset)` so that a value in `unit`
+/// is `value * scale + offset` in the quantity's base unit (V, Pa, K, s, Hz, A,
+/// Ohm). Accepts the SI prefixes n, u/µ/μ, m, c, k and M on prefixable units.
+fn base_unit(unit: &str) -> Option<(&'static str, f64, f64)> {
+    match unit {
+        "mmHg" => return Some(("Pa", 133.322_387_415, 0.0)),
+        "cmH2O" => return Some(("Pa", 98.0665, 0.0)),
+        "mbar" => return Some(("Pa", 100.0, 0.0)),
+        "degC" | "°C" => return Some(("K", 1.0, 273.15)),
+        "degF" | "°F" => return Some(("K", 5.0 / 9.0, 273.15 - 32.0 * 5.0 / 9.0)),
+        "K" => return Some(("K", 1.0, 0.0)),
+        "bpm" | "/min" => return Some(("Hz", 1.0 / 60.0, 0.0)),
+        "min" => return Some(("s", 60.0, 0.0)),
+        _ => {}
+    }
+    let prefixable = |base: &str| -> Option<&'static str> {
+        ["V", "Pa", "s", "Hz", "A", "Ohm"].into_iter().find(|known| *known == base)
+    };
+    if let Some(base) = prefixable(unit) {
+        return Some((base, 1.0, 0.0));
+    }
+    let mut chars = unit.chars();
+    let prefix: f64 = match chars.next()? {
+        'n' => 1e-9,
+        'u' | 'µ' | 'μ' => 1e-6,
+        'm' => 1e-3,
+        'c' => 1e-2,
+        'k' => 1e3,
+        'M' => 1e6,
+        _ => return None
+    };
+    prefixable(chars.as_str()).map(|base| (base, prefix, 0.0))
+}
+
+
+/// The `(scale, offset)` that converts a value in unit `from` to unit `to` as
+/// `value * scale + offset`, or `None` if either unit is unknown or they
+/// measure different quantities.
+pub fn unit_conversion(from: &str, to: &str) -> Option<(f64, f64)> {
+    let (from_quantity, from_scale, from_offset) = base_unit(from.trim())?;
+    let (to_quantity, to_scale, to_offset) = base_unit(to.trim())?;
+    if from_quantity != to_quantity {
+        return None;
+    }
+    Some((from_scale / to_scale, (from_offset - to_offset) / to_scale))
+}
+
+
+/// Rewrites every ordinary signal's physical bounds and dimension into
+/// `target`. Scaling is linear, so converting the bounds converts every value.
+/// Returns the labels of signals whose dimension can't be converted; those
+/// are left as they are.
+pub fn convert_signal_units(signals: &mut [Signal], target: &str) -> Vec<String> {
+    let mut unconverted: Vec<String> = Vec::new();
+    for signal in signals.iter_mut().filter(|signal| !signal.is_annotation()) {
+        match unit_conversion(&signal.dimension, target) {
+            Some((scale, offset)) => {
+                signal.bounds.physical_min = signal.bounds.physical_min * scale + offset;
+                signal.bounds.physical_max = signal.bounds.physical_max * scale + offset;
+                signal.dimension = target.to_string();
+            },
+            None => unconverted.push(signal.label.clone())
+        }
+    }
+    unconverted
+}
+
+
+/// Parses the fixed header and the per-signal headers, leaving `reader` at the
+/// start of the first data record.
+pub fn read_header<R: Read>(reader: &mut R) -> Result<EdfHeader, EdfError> {
+    read_header_with_fallback_date(reader, None)
+}
+
+
+/// Like [`read_header`], but an unparsable or impossible start date is
+/// replaced by `fallback_date` (with a warning) instead of failing. Either
+/// way, a start date of "X" takes the recording's Startdate if it has one.
+pub fn read_header_with_fallback_date<R: Read>(reader: &mut R, fallback_date: Option<NaiveDate>) -> Result<EdfHeader, EdfError> {
+    let patient_info: Option<PatientInfo> = PatientInfo::parse(&get_patient_field(reader)?);
+    let recording_info: Option<RecordingInfo> = RecordingInfo::parse(&get_recording_field(reader)?);
+    let mut warnings: Vec<String> = Vec::new();
+    if let Some(info) = patient_info.as_ref().filter(|info| info.birthdate != "X" && info.birthdate().is_none()) {
+        warnings.push(format!("patient birthdate '{}' isn't a dd-MMM-yyyy date", info.birthdate));
+    }
+    let date_string: String = get_start_date_field(reader)?;
+    let parsed_date: Result<NaiveDate, EdfError> = parse_start_date(&date_string);
+    let date_is_valid: bool = parsed_date.is_ok();
+    // EDF+ lets anonymized files blank the header date as "X" and keep the
+    // real one only in the recording's Startdate subfield. Any other date
+    // that can't be parsed is a malformed header.
+    let recording_date: Option<NaiveDate> = recording_info.as_ref().and_then(RecordingInfo::start_date);
+    let date: NaiveDate = match (parsed_date, recording_date, fallback_date) {
+        (Ok(date), _, _) => date,
+        (Err(_), Some(recording_date), _) if date_string.trim() == "X" => recording_date,
+        (Err(_), _, Some(fallback_date)) => {
+            warnings.push(format!("invalid start date '{}', using {} instead", date_string.trim(), fallback_date.format("%Y-%m-%d")));
+            fallback_date
+        },
+        (Err(error), _, None) => return Err(error)
+    };
+    let time: NaiveTime = get_start_time(reader)?;
+    let header_bytes: Option<u64> = get_header_bytes(reader)?;
+    let num_records: usize = get_num_records(reader)?;
+    let record_duration: f64 = get_record_duration(reader)?;
+    let num_signals: usize = get_num_signals(reader)?;
+    let signals: Vec<Signal> = get_signals(reader, num_signals)?;
+    warnings.extend(check_signal_bounds(&signals));
+    // EDF+ only allows records without duration in files that hold nothing
+    // but annotations. With samples, every row would get the same timestamp.
+    if (!record_duration.is_finite() || record_duration <= 0.0) && signals.iter().any(|s| !s.is_annotation() && s.num_samples > 0) {
+        return Err(EdfError::InvalidHeader(format!(
+            "the record duration is {} s, so the samples can't be placed on a timeline; only annotation-only files may have records without duration",
+            record_duration
+        )));
+    }
+
+    // The declared header size says where the records start. It can only
+    // add padding after the signal headers, not overlap them.
+    let signal_headers_end: u64 = 256 * (num_signals as u64 + 1);
+    let data_offset: u64 = match header_bytes {
+        Some(declared) if declared == signal_headers_end => declared,
+        Some(declared) if declared > signal_headers_end => {
+            warnings.push(format!(
+                "the header declares {} bytes but its {} signals take {}; records are read from byte {}",
+                declared, num_signals, signal_headers_end, declared
+            ));
+            skip(reader, declared - signal_headers_end)?;
+            declared
+        },
+        Some(declared) => {
+            warnings.push(format!(
+                "the header declares {} bytes, less than the {} its {} signals take; records are read from byte {}",
+                declared, signal_headers_end, num_signals, signal_headers_end
+            ));
+            signal_headers_end
+        },
+        None => {
+            warnings.push(format!("the header size isn't a number; records are read from byte {}", signal_headers_end));
+            signal_headers_end
+        }
+    };
+
+    // A fallback date never matches, so only compare a real header date.
+    if let Some(info) = recording_info.as_ref().filter(|_| date_is_valid) {
+        if let Some(recording_date) = info.start_date() {
+            if recording_date != date {
+                warnings.push(format!(
+                    "recording Startdate {} doesn't match the header start date {}",
+                    info.startdate, date.format("%Y-%m-%d")
+                ));
+            }
+        }
+    }
+
+    Ok(EdfHeader {
+        patient_info,
+        start: NaiveDateTime::new(date, time),
+        recording_info,
+        num_records,
+        record_duration,
+        signals,
+        data_offset,
+        warnings
+    })
+}
+
+
+/// Byte order of the samples. EDF mandates little-endian; big-endian is an
+/// escape hatch for nonstandard exporters.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Endian {
+    Little,
+    Big
+}
+
+
+/// Reads `capacity` little-endian samples, i.e. one data record when
+/// `capacity` is the sum of the signals' samples per record.
+pub fn read_record_samples<R: Read>(reader: &mut R, capacity: usize) -> Result<Vec<i16>, EdfError> {
+    read_record_samples_with_endian(reader, capacity, Endian::Little)
+}
+
+
+/// Like [`read_record_samples`] with the given byte order.
+pub fn read_record_samples_with_endian<R: Read>(reader: &mut R, capacity: usize, endian: Endian) -> Result<Vec<i16>, EdfError> {
+    // The capacity comes straight from the header, so a corrupt file could
+    // otherwise ask for an enormous allocation before the first read fails.
+    let mut values: Vec<i16> = Vec::with_capacity(capacity.min(MAX_PREALLOCATED_SAMPLES));
+    for _ in 0..capacity {
+        let value: i16 = match endian {
+            Endian::Little => reader.by_ref().read_i16::<LittleEndian>()?,
+            Endian::Big => reader.by_ref().read_i16::<BigEndian>()?
+        };
+        values.push(value);
+    }
+    Ok(values)
+}
+
+
+/// Heuristic for samples read with the wrong byte order: physiological
+/// signals change little from one sample to the next, so if swapping the
+/// bytes makes a run of samples far smoother, it was probably misread.
+pub fn looks_byte_swapped(values: &[i16]) -> bool {
+    if values.len() < 16 {
+        return false;
+    }
+    let roughness = |values: &mut dyn Iterator<Item = i16>| -> f64 {
+        let values: Vec<f64> = values.map(f64::from).collect();
+        values.windows(2).map(|pair| (pair[1] - pair[0]).abs()).sum::<f64>()
+    };
+    let as_read: f64 = roughness(&mut values.iter().copied());
+    let swapped: f64 = roughness(&mut values.iter().map(|value| value.swap_bytes()));
+    swapped * 8.0 < as_read
+}
+
+
+/// Formats a header number into at most `width` characters, dropping
+/// decimals as needed. Fails when even the integer part doesn't fit.
+pub fn format_header_number(value: f64, width: usize) -> Result<String, EdfError> {
+    let plain: String = value.to_string();
+    if plain.len() <= width {
+        return Ok(plain);
+    }
+    for precision in (0..width).rev() {
+        let rounded: String = format!("{:.*}", precision, value);
+        if rounded.len() <= width {
+            return Ok(rounded);
+        }
+    }
+    Err(EdfError::EdfWrite(format!("{} doesn't fit in a {} character header field", value, width)))
+}
+
+
+// Pads or truncates a header field to its fixed width.
+fn write_field<W: Write>(writer: &mut W, value: &str, width: usize) -> Result<(), EdfError> {
+    let mut bytes: Vec<u8> = value.as_bytes().iter().copied().take(width).collect();
+    bytes.resize(width, b' ');
+    writer.write_all(&bytes)?;
+    Ok(())
+}
+
+
+/// De-identifies the identification fields at the start of `bytes`, a
+/// header as read into `header`: the patient code and name and the
+/// recording's admin code and technician become "X". The birthdate is
+/// shifted by `shift_days` when given and valid, and otherwise removed.
+/// With `shift_days`, the start date in both its fields moves too, so
+/// intervals between a patient's recordings survive. Only the first 184
+/// bytes change.
+pub fn anonymize_header(bytes: &mut [u8], header: &EdfHeader, shift_days: Option<i64>) -> Result<(), EdfError> {
+    if bytes.len() < 184 {
+        return Err(EdfError::EdfWrite("the header is shorter than its 184 fixed bytes".to_string()));
+    }
+    let shift = |date: NaiveDate| -> Option<NaiveDate> {
+        date.checked_add_signed(chrono::Duration::days(shift_days?))
+    };
+    let start: NaiveDate = match shift_days {
+        Some(_) => shift(header.start.date()).ok_or_else(|| EdfError::EdfWrite("the shifted start date is out of range".to_string()))?,
+        None => header.start.date()
+    };
+    if !(1985..=2084).contains(&start.year()) {
+        return Err(EdfError::EdfWrite(format!("start year {} can't be written to an EDF header", start.year())));
+    }
+
+    let patient: String = match &header.patient_info {
+        Some(info) => {
+            let birthdate: String = info.birthdate().and_then(shift).map(format_edf_plus_date).unwrap_or_else(|| "X".to_string());
+            format!("X {} {} X", info.sex, birthdate)
+        },
+        None => "X X X X".to_string()
+    };
+    let equipment: &str = header.recording_info.as_ref().map_or("X", |info| info.equipment.as_str());
+    let recording: String = format!("Startdate {} X X {}", format_edf_plus_date(start), equipment);
+
+    let mut fields: &mut [u8] = &mut bytes[8..176];
+    write_field(&mut fields, &patient, 80)?;
+    write_field(&mut fields, &recording, 80)?;
+    if shift_days.is_some() {
+        write_field(&mut fields, &start.format("%d.%m.%y").to_string(), 8)?;
+    }
+    Ok(())
+}
+
+
+/// Writes a plain EDF header for `header`. The start date must lie in
+/// 1985..=2084, the range the two-digit year field can express.
+pub fn write_header<W: Write>(writer: &mut W, header: &EdfHeader) -> Result<(), EdfError> {
+    let year: i32 = header.start.date().year();
+    if !(1985..=2084).contains(&year) {
+        return Err(EdfError::EdfWrite(format!("start year {} can't be written to an EDF header", year)));
+    }
+    let recording: String = match &header.recording_info {
+        Some(info) => format!("Startdate {} {} {} {} {}", info.startdate, info.admin_code, info.technician, info.equipment, info.additional),
+        None => format!("Startdate {} X X X", format_edf_plus_date(header.start.date()))
+    };
+    let num_signals: usize = header.signals.len();
+
+    write_field(writer, "0", 8)?;
+    write_field(writer, "X X X X", 80)?;
+    write_field(writer, recording.trim_end(), 80)?;
+    write_field(writer, &header.start.format("%d.%m.%y").to_string(), 8)?;
+    write_field(writer, &header.start.format("%H.%M.%S").to_string(), 8)?;
+    write_field(writer, &(256 * (num_signals + 1)).to_string(), 8)?;
+    write_field(writer, "", 44)?;
+    write_field(writer, &header.num_records.to_string(), 8)?;
+    write_field(writer, &format_header_number(header.record_duration, 8)?, 8)?;
+    write_field(writer, &num_signals.to_string(), 4)?;
+
+    for signal in &header.signals {
+        write_field(writer, &signal.label, 16)?;
+    }
+    for _ in &header.signals {
+        write_field(writer, "", 80)?;
+    }
+    for signal in &header.signals {
+        write_field(writer, &signal.dimension, 8)?;
+    }
+    let bounds: [fn(&Bounds) -> f64; 4] = [|b| b.physical_min, |b| b.physical_max, |b| b.digital_min, |b| b.digital_max];
+    for bound in bounds {
+        for signal in &header.signals {
+            write_field(writer, &format_header_number(bound(&signal.bounds), 8)?, 8)?;
+        }
+    }
+    for _ in &header.signals {
+        write_field(writer, "", 80)?;
+    }
+    for signal in &header.signals {
+        write_field(writer, &signal.num_samples.to_string(), 8)?;
+    }
+    for _ in &header.signals {
+        write_field(writer, "", 32)?;
+    }
+    Ok(())
+}
+
+
+/// Writes samples little-endian, the counterpart of [`read_record_samples`].
+pub fn write_record_samples<W: Write>(writer: &mut W, values: &[i16]) -> Result<(), EdfError> {
+    for &value in values {
+        writer.write_i16::<LittleEndian>(value)?;
+    }
+    Ok(())
+}
+
+
+/// Settings for [`edf_to_csv`], made with [`ConversionOptions::builder`].
+/// The defaults give the same CSV as the command line tool without flags.
+#[derive(Clone, Debug)]
+pub struct ConversionOptions {
+    delimiter: u8,
+    missing_value: String,
+    datetime_format: String,
+    timestamp_column: String,
+    raw_digital: bool,
+    endian: Endian,
+    timeline_signal: Option<String>
+}
+impl Default for ConversionOptions {
+    fn default() -> Self {
+        ConversionOptions {
+            delimiter: b',',
+            missing_value: String::new(),
+            datetime_format: DEFAULT_DATETIME_FORMAT.to_string(),
+            timestamp_column: "timestamp".to_string(),
+            raw_digital: false,
+            endian: Endian::Little,
+            timeline_signal: None
+        }
+    }
+}
+impl ConversionOptions {
+    pub fn builder() -> ConversionOptionsBuilder {
+        ConversionOptionsBuilder { options: ConversionOptions::default() }
+    }
+
+    /// Field separator of the CSV.
+    pub fn delimiter(&self) -> u8 {
+        self.delimiter
+    }
+
+    /// Written in place of a missing sample.
+    pub fn missing_value(&self) -> &str {
+        &self.missing_value
+    }
+
+    /// strftime-style pattern for the timestamp column.
+    pub fn datetime_format(&self) -> &str {
+        &self.datetime_format
+    }
+
+    /// Name of the timestamp column in the header row.
+    pub fn timestamp_column(&self) -> &str {
+        &self.timestamp_column
+    }
+
+    /// Whether the digital samples are written as stored instead of scaled.
+    pub fn raw_digital(&self) -> bool {
+        self.raw_digital
+    }
+
+    /// Byte order of the data records.
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    /// Label of the signal whose samples set the rows, or None for the
+    /// highest-rate signal; see [`Timeline`].
+    pub fn timeline_signal(&self) -> Option<&str> {
+        self.timeline_signal.as_deref()
+    }
+
+    /// What the row of dimensions says about the timestamp column.
+    pub fn timestamp_unit(&self) -> &str {
+        if self.datetime_format == DEFAULT_DATETIME_FORMAT {
+            "YYYY-MM-DD hh:mm:ss"
+        } else {
+            &self.datetime_format
+        }
+    }
+
+    /// A signal's part of a record as values: scaled to physical units, or
+    /// the digital samples as stored with [`ConversionOptionsBuilder::raw_digital`].
+    /// The i16::MIN sentinel is missing either way.
+    pub fn scale_samples(&self, signal: &Signal, raw_values: &[i16]) -> Vec<Option<f64>> {
+        if self.raw_digital {
+            raw_values.iter().map(|&raw| (raw != i16::MIN).then_some(raw as f64)).collect()
+        } else {
+            signal.scale_samples(raw_values)
+        }
+    }
+}
+
+
+/// Builds [`ConversionOptions`]; every setting not given keeps its default.
+#[derive(Clone, Debug)]
+pub struct ConversionOptionsBuilder {
+    options: ConversionOptions
+}
+impl ConversionOptionsBuilder {
+    pub fn delimiter(&mut self, delimiter: u8) -> &mut Self {
+        self.options.delimiter = delimiter;
+        self
+    }
+
+    pub fn missing_value(&mut self, missing_value: &str) -> &mut Self {
+        self.options.missing_value = missing_value.to_string();
+        self
+    }
+
+    pub fn datetime_format(&mut self, datetime_format: &str) -> &mut Self {
+        self.options.datetime_format = datetime_format.to_string();
+        self
+    }
+
+    pub fn timestamp_column(&mut self, timestamp_column: &str) -> &mut Self {
+        self.options.timestamp_column = timestamp_column.to_string();
+        self
+    }
+
+    pub fn raw_digital(&mut self, raw_digital: bool) -> &mut Self {
+        self.options.raw_digital = raw_digital;
+        self
+    }
+
+    pub fn endian(&mut self, endian: Endian) -> &mut Self {
+        self.options.endian = endian;
+        self
+    }
+
+    pub fn timeline_signal(&mut self, timeline_signal: Option<&str>) -> &mut Self {
+        self.options.timeline_signal = timeline_signal.map(String::from);
+        self
+    }
+
+    pub fn build(&self) -> ConversionOptions {
+        self.options.clone()
+    }
+}
+
+
+/// The rows a file's data signals are written in: one per sample of the
+/// reference signal, the highest-rate one unless
+/// [`ConversionOptionsBuilder::timeline_signal`] names another. Every other
+/// signal shows its latest sample at or before the row's time, so slower
+/// signals repeat and faster ones are thinned. When all signals share a rate
+/// this is one row per sample.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timeline {
+    rows_per_record: usize,
+    record_duration: f64
+}
+impl Timeline {
+    /// The timeline of `columns`. Signals without samples don't count; they
+    /// only have blank cells.
+    pub fn new(columns: &[&Signal], record_duration: f64, options: &ConversionOptions) -> Result<Self, EdfError> {
+        let reference: Option<&&Signal> = match options.timeline_signal() {
+            None => columns.iter().max_by_key(|s| s.num_samples),
+            Some(label) => match columns.iter().find(|s| s.label == label) {
+                Some(signal) => Some(signal),
+                None => return Err(EdfError::InvalidArgument(format!("no signal '{}' to set the timeline", label)))
+            }
+        };
+        Ok(Timeline {rows_per_record: reference.map_or(0, |signal| signal.num_samples), record_duration})
+    }
+
+    /// A single signal's own timeline, a row per sample.
+    pub fn of_signal(signal: &Signal, record_duration: f64) -> Self {
+        Timeline {rows_per_record: signal.num_samples, record_duration}
+    }
+
+    pub fn rows_per_record(&self) -> usize {
+        self.rows_per_record
+    }
+
+    /// Time between two rows, to the nanosecond: a rate like 256 Hz doesn't
+    /// divide into whole milliseconds, and stepping by a truncated interval
+    /// makes the timestamps drift.
+    pub fn row_interval(&self) -> chrono::Duration {
+        chrono::Duration::nanoseconds((1e9 * self.record_duration / self.rows_per_record.max(1) as f64).round() as i64)
+    }
+
+    /// The exact time between two rows in milliseconds, or None without rows.
+    pub fn row_interval_ms(&self) -> Option<f64> {
+        (self.rows_per_record > 0).then(|| 1000.0 * self.record_duration / self.rows_per_record as f64)
+    }
+
+    /// How far after the start the rows of `record` begin, stepped the way
+    /// the rows are; None if that doesn't fit a [`chrono::Duration`].
+    pub fn record_offset(&self, record: usize) -> Option<chrono::Duration> {
+        record
+            .checked_mul(self.rows_per_record)
+            .and_then(|rows| i64::try_from(rows).ok())
+            .zip(self.row_interval().num_nanoseconds())
+            .and_then(|(rows, interval)| rows.checked_mul(interval))
+            .map(chrono::Duration::nanoseconds)
+    }
+
+    /// The value that `row` of a record shows for a signal with `values` in
+    /// that record; always None for a signal without samples.
+    pub fn sample(&self, row: usize, values: &[Option<f64>]) -> Option<f64> {
+        values.get(row * values.len() / self.rows_per_record.max(1)).copied().flatten()
+    }
+}
+
+
+/// Converts the EDF file read from `input` into a CSV written to `output`:
+/// a header row of labels, a row of dimensions and then a row per sample of
+/// the [`Timeline`], led by its timestamp. Annotation signals are left out
+/// and signals without samples give a blank column. Returns the number of
+/// data rows written.
+pub fn edf_to_csv<R: Read, W: Write>(mut input: R, output: W, options: &ConversionOptions) -> Result<usize, EdfError> {
+    let header: EdfHeader = read_header(&mut input)?;
+    let record_size: usize = header.signals.iter().map(|s| s.num_samples).sum();
+    let columns: Vec<&Signal> = header.signals.iter().filter(|s| !s.is_annotation()).collect();
+    let timeline: Timeline = Timeline::new(&columns, header.record_duration, options)?;
+
+    let mut writer: csv::Writer<W> = csv::WriterBuilder::new().delimiter(options.delimiter).from_writer(output);
+    let mut row: Vec<String> = vec![options.timestamp_column.clone()];
+    row.extend(columns.iter().map(|s| s.label.clone()));
+    writer.write_record(&row)?;
+    row.clear();
+    row.push(options.timestamp_unit().to_string());
+    row.extend(columns.iter().map(|s| s.dimension.clone()));
+    writer.write_record(&row)?;
+
+    let mut timestamp: NaiveDateTime = header.start;
+    let mut rows_written: usize = 0;
+    for record in 0..header.num_records {
+        let values: Vec<i16> = read_record_samples_with_endian(&mut input, record_size, options.endian)?;
+        if record == 0 {
+            timestamp += fractional_start(&header.signals, &values, 0, header.record_duration, options.endian);
+        }
+        let scaled: Vec<Vec<Option<f64>>> = data_samples(&header.signals, &values)
+            .map(|(signal, raw_values)| options.scale_samples(signal, raw_values).into_iter().map(|value| value.filter(|value| value.is_finite())).collect())
+            .collect();
+        for i in 0..timeline.rows_per_record() {
+            row.clear();
+            row.push(timestamp.format(&options.datetime_format).to_string());
+            for signal_values in &scaled {
+                let value: Option<f64> = timeline.sample(i, signal_values);
+                row.push(value.map_or_else(|| options.missing_value.clone(), |value| value.to_string()));
+            }
+            writer.write_record(&row)?;
+            timestamp += timeline.row_interval();
+            rows_written += 1;
+        }
+    }
+    writer.flush()?;
+    Ok(rows_written)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Range;
+
+    fn test_signal(label: &str, dimension: &str, num_samples: usize) -> Signal {
+        Signal {
+            label: label.to_string(),
+            dimension: dimension.to_string(),
+            bounds: Bounds {digital_min: -2048.0, digital_max: 2047.0, physical_min: -500.0, physical_max: 500.0},
+            num_samples
+        }
+    }
+
+    fn test_header(signals: Vec<Signal>, num_records: usize, record_duration: f64) -> EdfHeader {
+        EdfHeader {
+            start: NaiveDate::from_ymd_opt(2023, 1, 15).unwrap().and_hms_opt(8, 30, 0).unwrap(),
+            patient_info: None,
+            recording_info: None,
+            num_records,
+            record_duration,
+            data_offset: 256 * (signals.len() as u64 + 1),
+            signals,
+            warnings: Vec::new()
+        }
+    }
+
+    fn header_bytes(header: &EdfHeader) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        write_header(&mut bytes, header).unwrap();
+        bytes
+    }
+
+    // Swaps the spaces padding the fields in `range` for `padding`.
+    fn repad(bytes: &mut [u8], range: Range<usize>, padding: u8) {
+        for byte in bytes[range].iter_mut().filter(|byte| **byte == b' ') {
+            *byte = padding;
+        }
+    }
+
+    #[test]
+    fn null_padded_numeric_fields_parse_like_space_padded_ones() {
+        let header: EdfHeader = test_header(vec![test_signal("EEG", "uV", 4), test_signal("Resp", "mV", 2)], 3, 0.5);
+        let spaced: Vec<u8> = header_bytes(&header);
+        let mut padded: Vec<u8> = spaced.clone();
+        // Header size, number of records, record duration, number of signals.
+        repad(&mut padded, 184..192, b'\0');
+        repad(&mut padded, 236..244, b'\0');
+        repad(&mut padded, 244..252, b'\r');
+        repad(&mut padded, 252..256, b'\n');
+        // With two signals, the four bounds fields of both take bytes
+        // 464..528 and their numbers of samples 688..704.
+        repad(&mut padded, 464..528, b'\0');
+        repad(&mut padded, 688..704, b'\0');
+        assert_ne!(padded, spaced);
+
+        let expected: EdfHeader = read_header(&mut spaced.as_slice()).unwrap();
+        let parsed: EdfHeader = read_header(&mut padded.as_slice()).unwrap();
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", expected));
+        assert_eq!(parsed.num_records, 3);
+        assert_eq!(parsed.signals[1].num_samples, 2);
+        assert!(parsed.warnings.is_empty(), "{:?}", parsed.warnings);
+    }
+
+    #[test]
+    fn signal_headers_read_in_one_block_round_trip() {
+        let mut signals: Vec<Signal> = vec![test_signal("EEG Fp1-A2", "uV", 4), test_signal("Resp", "mV", 2), test_signal("SpO2", "%", 1)];
+        signals[1].bounds = Bounds {digital_min: 0.0, digital_max: 4095.0, physical_min: -1.5, physical_max: 2.25};
+        let header: EdfHeader = test_header(signals, 2, 1.0);
+        let records: [[i16; 7]; 2] = [[1, -2, 3, -4, 100, 200, 97], [5, 6, 7, 8, 300, 400, 98]];
+        let mut bytes: Vec<u8> = header_bytes(&header);
+        for record in &records {
+            write_record_samples(&mut bytes, record).unwrap();
+        }
+
+        let mut reader: &[u8] = bytes.as_slice();
+        let parsed: EdfHeader = read_header(&mut reader).unwrap();
+        assert_eq!(format!("{:?}", parsed.signals), format!("{:?}", header.signals));
+        assert_eq!(parsed.data_offset, 256 * 4);
+        // The header read stops exactly where the records begin.
+        for record in &records {
+            assert_eq!(read_record_samples(&mut reader, 7).unwrap(), record);
+        }
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn zero_record_duration_with_samples_is_rejected() {
+        let header: EdfHeader = test_header(vec![test_signal("EEG", "uV", 4)], 2, 0.0);
+        let mut bytes: Vec<u8> = header_bytes(&header);
+        bytes.extend(std::iter::repeat_n(0, 2 * 4 * 2));
+
+        let error: EdfError = read_header(&mut bytes.as_slice()).unwrap_err();
+        assert!(matches!(&error, EdfError::InvalidHeader(message) if message.contains("record duration is 0 s")), "{:?}", error);
+        let mut csv: Vec<u8> = Vec::new();
+        let error: EdfError = edf_to_csv(bytes.as_slice(), &mut csv, &ConversionOptions::default()).unwrap_err();
+        assert!(matches!(error, EdfError::InvalidHeader(_)), "{:?}", error);
+    }
+
+    #[test]
+    fn zero_record_duration_is_allowed_for_annotations_only() {
+        let header: EdfHeader = test_header(vec![test_signal("EDF Annotations", "", 8)], 1, 0.0);
+        let parsed: EdfHeader = read_header(&mut header_bytes(&header).as_slice()).unwrap();
+        assert_eq!(parsed.record_duration, 0.0);
+    }
+
+    #[test]
+    fn timeline_steps_by_the_exact_interval() {
+        let signal: Signal = test_signal("EEG", "uV", 256);
+        let timeline: Timeline = Timeline::of_signal(&signal, 1.0);
+        assert_eq!(timeline.row_interval().num_nanoseconds(), Some(3_906_250));
+        assert_eq!(timeline.row_interval_ms(), Some(3.90625));
+        // After a second of rows the timestamps are back on the second.
+        assert_eq!(timeline.record_offset(1).map(|offset| offset.num_milliseconds()), Some(1000));
+        assert_eq!(Timeline::of_signal(&test_signal("Resp", "mV", 3), 1.0).row_interval().num_nanoseconds(), Some(333_333_333));
+    }
+
+    #[test]
+    fn mixed_rate_signals_are_aligned_to_the_timeline() {
+        let header: EdfHeader = test_header(vec![test_signal("EEG", "uV", 4), test_signal("Resp", "mV", 2), test_signal("Off", "uV", 0)], 1, 1.0);
+        let mut bytes: Vec<u8> = header_bytes(&header);
+        write_record_samples(&mut bytes, &[-2048, 0, 1024, 2047, -2048, 2047]).unwrap();
+
+        let mut csv: Vec<u8> = Vec::new();
+        assert_eq!(edf_to_csv(bytes.as_slice(), &mut csv, &ConversionOptions::default()).unwrap(), 4);
+        let rows: Vec<&str> = std::str::from_utf8(&csv).unwrap().lines().skip(2).collect();
+        let values: Vec<&str> = rows.iter().map(|row| &row[row.find(',').unwrap() + 1..]).collect();
+        // Resp has a sample for every second EEG row and Off has none.
+        let bounds: &Bounds = &header.signals[0].bounds;
+        assert_eq!(values, [
+            "-500,-500,".to_string(),
+            format!("{},-500,", bounds.scale(&0).unwrap()),
+            format!("{},500,", bounds.scale(&1024).unwrap()),
+            "500,500,".to_string()
+        ]);
+        assert!(rows[1].starts_with("2023-01-15T08:30:00.250,"));
+
+        let options: ConversionOptions = ConversionOptions::builder().timeline_signal(Some("Resp")).build();
+        let mut csv: Vec<u8> = Vec::new();
+        assert_eq!(edf_to_csv(bytes.as_slice(), &mut csv, &options).unwrap(), 2);
+        let options: ConversionOptions = ConversionOptions::builder().timeline_signal(Some("ECG")).build();
+        let error: EdfError = edf_to_csv(bytes.as_slice(), &mut Vec::new(), &options).unwrap_err();
+        assert!(matches!(error, EdfError::InvalidArgument(_)), "{:?}", error);
+    }
+
+    #[test]
+    fn parse_bound_accepts_padding_and_signs() {
+        assert_eq!(parse_bound("EEG", "physical minimum", "+100").unwrap(), 100.0);
+        assert_eq!(parse_bound("EEG", "physical minimum", " -1.0E+2 ").unwrap(), -100.0);
+        assert_eq!(parse_bound("EEG", "physical minimum", "\t250.5\0\0\r\n").unwrap(), 250.5);
+    }
+
+    #[test]
+    fn parse_bound_takes_a_single_comma_as_decimal_separator() {
+        assert_eq!(parse_bound("EEG", "physical minimum", "-1,5").unwrap(), -1.5);
+        assert_eq!(parse_bound("EEG", "physical minimum", "0,25E+3").unwrap(), 250.0);
+    }
+
+    #[test]
+    fn parse_bound_rejects_ambiguous_commas() {
+        for value in ["1,000", "-12,345", "1,000,000", "1,5,0", "1.5,0", "1,000.5"] {
+            assert!(parse_bound("EEG", "physical minimum", value).is_err(), "'{}' was accepted", value);
+        }
+    }
+
+    #[test]
+    fn parse_bound_rejects_inner_whitespace() {
+        for value in ["1 2", "- 1.0", "1.0E+ 2", "1\t0"] {
+            assert!(parse_bound("EEG", "physical minimum", value).is_err(), "'{}' was accepted", value);
+        }
+    }
+}
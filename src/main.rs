@@ -1,355 +1,4881 @@
-extern crate byteorder;
+extern crate chrono;
 extern crate csv;
-extern crate datetime;
-extern  crate thiserror;
+extern crate edf_to_csv;
+extern crate serde;
+extern crate serde_json;
+extern crate zip;
 
 use std::env;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::ops::Range;
 use std::fs::{self, File, OpenOptions};
-use std::io::{BufReader, Read};
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::process;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{self, Instant};
 use std::path::{Path, PathBuf};
 
-use byteorder::{ReadBytesExt, LittleEndian};
-use csv::{QuoteStyle, Writer, WriterBuilder};
-use datetime::{Duration, Instant, ISO, LocalDate, LocalDateTime, LocalTime, Month};
-use thiserror::Error;
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use flate2::write::GzEncoder;
+use glob::Pattern;
+use flate2::Compression;
+use csv::{QuoteStyle, Reader, ReaderBuilder, StringRecord, StringRecordsIntoIter, Writer, WriterBuilder};
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection};
+use edf_to_csv::{annotation_bytes, anonymize_header, check_missing_units, check_unit_prefixes, convert_signal_units, data_samples, format_header_number, fractional_start, is_standard_text, looks_byte_swapped, parse_tals, read_header_with_fallback_date, read_record_samples_with_endian, time_keeping_onset, unit_conversion, write_header, write_record_samples, Bounds, ConversionOptions, ConversionOptionsBuilder, DEFAULT_DATETIME_FORMAT, Endian, EdfError, EdfHeader, PatientInfo, RecordingInfo, RetryReader, Signal, Tal, Timeline};
+use serde::{Deserialize, Serialize};
+use zip::ZipArchive;
 
 
-struct Bounds {
-    digital_min: f32,
-    digital_max: f32,
-    physical_min: f32,
-    physical_max: f32
+// With `--low-memory`, a ZIP entry is streamed in at most this many chunks of
+// this size at a time instead of being decompressed into memory.
+const ZIP_STREAM_CHUNKS: usize = 4;
+const ZIP_STREAM_CHUNK_SIZE: usize = 64 * 1024;
+// With `--threads-per-file`, about this many rows are formatted per batch.
+const PARALLEL_BATCH_ROWS: usize = 16 * 1024;
+// Number of equal bins in the `--stats` histograms.
+const STATS_BINS: usize = 10;
+// The input path that stands for stdin, and the name its output gets.
+const STDIN_PATH: &str = "-";
+const STDIN_NAME: &str = "stdin";
+
+
+// Stdin can only be read once but may be opened several times (sorting,
+// listing, converting), so it is buffered whole on first use. With
+// `--low-memory` it's streamed instead, and opening it again is an error.
+static STDIN_CONTENTS: OnceLock<Result<Vec<u8>, String>> = OnceLock::new();
+static STDIN_STREAMED: AtomicBool = AtomicBool::new(false);
+
+
+type EdfReader = BufReader<RetryReader<EdfSource>>;
+
+
+// What an EdfReader reads from. Local files and inputs already in memory can
+// skip records with a seek; streams (URLs, stdin and ZIP entries with
+// `--low-memory`) have to read past them.
+enum EdfSource {
+    File(File),
+    Memory(Cursor<Cow<'static, [u8]>>),
+    Stream(Box<dyn Read + Send>)
+}
+impl EdfSource {
+    // The input's size in bytes, if it can be seeked.
+    fn seekable_size(&self) -> io::Result<Option<u64>> {
+        match self {
+            EdfSource::File(file) => Ok(Some(file.metadata()?.len())),
+            EdfSource::Memory(cursor) => Ok(Some(cursor.get_ref().len() as u64)),
+            EdfSource::Stream(_) => Ok(None)
+        }
+    }
+}
+impl Read for EdfSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            EdfSource::File(file) => file.read(buf),
+            EdfSource::Memory(cursor) => cursor.read(buf),
+            EdfSource::Stream(stream) => stream.read(buf)
+        }
+    }
 }
-impl Bounds {
-    fn scale(&self, &value: &i16) -> Option<f32> {
-        if value == i16::MIN {
-            return None;
+impl Seek for EdfSource {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        match self {
+            EdfSource::File(file) => file.seek(position),
+            EdfSource::Memory(cursor) => cursor.seek(position),
+            EdfSource::Stream(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "the input is a stream and can't seek"))
         }
-        let value: f32 = value as f32;
-        let digital_range: f32 = self.digital_max - self.digital_min;
-        let physical_range: f32 = self.physical_max - self.physical_min;
+    }
+}
 
-        Some(((value - self.digital_min) * physical_range / digital_range) + self.physical_min)
+
+// Moves `bytes` further into the input, by seeking where the input allows it
+// and by reading past the bytes otherwise. False if the input ends first.
+fn skip_input(reader: &mut EdfReader, bytes: u64) -> io::Result<bool> {
+    match reader.get_ref().get_ref().seekable_size()? {
+        Some(size) => {
+            let position: u64 = reader.stream_position()?;
+            if position.saturating_add(bytes) > size {
+                return Ok(false);
+            }
+            reader.seek_relative(bytes as i64)?;
+            Ok(true)
+        },
+        None => Ok(io::copy(&mut reader.by_ref().take(bytes), &mut io::sink())? == bytes)
     }
 }
 
 
-struct Signal {
-    label: String,
-    dimension: String,
-    bounds: Bounds,
-    num_samples: usize
+// A record's index, row timestamps and per-signal scaled samples.
+type ScaledRecord = (usize, Vec<NaiveDateTime>, Vec<Vec<Option<f64>>>);
+// A record's index, samples as read and per-signal scaled samples, waiting in
+// `GapFiller` until its gaps are filled.
+type PendingRecord = (usize, Vec<i16>, Vec<Vec<Option<f64>>>);
+
+
+#[derive(Clone, Copy, PartialEq)]
+enum Interpolation {
+    None,
+    Linear
+}
+
+
+#[derive(Clone, Copy, PartialEq)]
+enum SortOrder {
+    None,
+    Path,
+    StartTime,
+    Size
+}
+
+
+#[derive(Clone, Copy, PartialEq)]
+enum SchemaFormat {
+    Json,
+    Sql
+}
+
+
+// A header value that `--inline-metadata` repeats in a column of every row.
+#[derive(Clone, Copy, PartialEq)]
+enum MetadataField {
+    File,
+    Start,
+    StartDate,
+    // The whole EDF+ recording identification, which holds no patient data.
+    RecordingId,
+    AdminCode,
+    Technician,
+    Equipment
+}
+impl MetadataField {
+    fn parse(value: &str) -> Result<Self, EdfError> {
+        match value {
+            "file" => Ok(MetadataField::File),
+            "start" => Ok(MetadataField::Start),
+            "start_date" => Ok(MetadataField::StartDate),
+            "recording_id" => Ok(MetadataField::RecordingId),
+            "admin_code" => Ok(MetadataField::AdminCode),
+            "technician" => Ok(MetadataField::Technician),
+            "equipment" => Ok(MetadataField::Equipment),
+            other => Err(EdfError::InvalidArgument(format!("unknown metadata field '{}'", other)))
+        }
+    }
+
+    // The field's value for a file, or None where the header leaves it
+    // unknown: plain EDF has no recording subfields and EDF+ writes "X".
+    fn value(self, file_path: &Path, header: &EdfHeader) -> Option<String> {
+        let info: Option<&RecordingInfo> = header.recording_info.as_ref();
+        let subfield = |value: &str| Some(value.to_string()).filter(|value| value != "X");
+        match self {
+            MetadataField::File => Some(file_path.to_string_lossy().to_string()),
+            MetadataField::Start => Some(header.start.format(DEFAULT_DATETIME_FORMAT).to_string()),
+            MetadataField::StartDate => Some(header.start.format("%Y-%m-%d").to_string()),
+            MetadataField::RecordingId => info.map(|info| {
+                format!("Startdate {} {} {} {} {}", info.startdate, info.admin_code, info.technician, info.equipment, info.additional).trim_end().to_string()
+            }),
+            MetadataField::AdminCode => info.and_then(|info| subfield(&info.admin_code)),
+            MetadataField::Technician => info.and_then(|info| subfield(&info.technician)),
+            MetadataField::Equipment => info.and_then(|info| subfield(&info.equipment))
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, PartialEq)]
+enum Rounding {
+    HalfEven,
+    HalfUp,
+    Truncate
+}
+
+
+#[derive(Clone, Copy, PartialEq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal
+}
+
+
+// A `--missing-if` condition on the scaled value, e.g. "<=-999" or "abs>1e6".
+#[derive(Clone, Copy)]
+struct MissingCondition {
+    absolute: bool,
+    comparison: Comparison,
+    threshold: f64
 }
- 
+impl MissingCondition {
+    fn parse(value: &str) -> Result<Self, EdfError> {
+        let invalid = || EdfError::InvalidArgument(format!("can't parse '{}' as a condition like <=-999 or abs>1e6", value));
+        let condition: &str = value.trim();
+        let (absolute, condition): (bool, &str) = match condition.strip_prefix("abs") {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, condition)
+        };
+        let operators: [(&str, Comparison); 6] = [
+            ("<=", Comparison::LessOrEqual),
+            (">=", Comparison::GreaterOrEqual),
+            ("==", Comparison::Equal),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+            ("=", Comparison::Equal)
+        ];
+        let (comparison, threshold): (Comparison, &str) = operators
+            .iter()
+            .find_map(|&(operator, comparison)| condition.strip_prefix(operator).map(|rest| (comparison, rest)))
+            .ok_or_else(invalid)?;
+        let threshold: f64 = threshold.trim().parse().map_err(|_| invalid())?;
+        Ok(MissingCondition { absolute, comparison, threshold })
+    }
 
-#[derive(Error, Debug)]
-enum EdfError {
-    #[error("Can't perform csv operation.")]
-    Csv(String),
-    #[error("Can't parse value to float.")]
-    ParseFloat(String),
-    #[error("Can't parse value to int.")]
-    ParseInt(String),
-    #[error("Can't perform I/O operation.")]
-    Io(String),
-    #[error("Can't parse datetime.")]
-    Datetime(String),
-    #[error("Number of signals in each sample don't match!")]
-    MismatchedSignals(String)
+    fn matches(&self, value: f64) -> bool {
+        let value: f64 = if self.absolute { value.abs() } else { value };
+        match self.comparison {
+            Comparison::Less => value < self.threshold,
+            Comparison::LessOrEqual => value <= self.threshold,
+            Comparison::Greater => value > self.threshold,
+            Comparison::GreaterOrEqual => value >= self.threshold,
+            Comparison::Equal => value == self.threshold
+        }
+    }
+}
 
 
+// An `--override-bounds` spec, e.g. "EEG:pmin=-250,pmax=250": the bounds it
+// names replace the header's for the signal with that label.
+struct BoundsOverride {
+    label: String,
+    physical_min: Option<f64>,
+    physical_max: Option<f64>,
+    digital_min: Option<f64>,
+    digital_max: Option<f64>
+}
+impl BoundsOverride {
+    fn parse(value: &str) -> Result<Self, EdfError> {
+        let invalid = || EdfError::InvalidArgument(format!("can't parse '{}' as bounds like EEG:pmin=-250,pmax=250", value));
+        let (label, assignments): (&str, &str) = value.rsplit_once(':').ok_or_else(invalid)?;
+        let mut bounds: BoundsOverride = BoundsOverride {
+            label: label.trim().to_string(),
+            physical_min: None,
+            physical_max: None,
+            digital_min: None,
+            digital_max: None
+        };
+        for assignment in assignments.split(',') {
+            let (key, number): (&str, &str) = assignment.split_once('=').ok_or_else(invalid)?;
+            let number: f64 = number.trim().parse().map_err(|_| invalid())?;
+            let field: &mut Option<f64> = match key.trim() {
+                "pmin" => &mut bounds.physical_min,
+                "pmax" => &mut bounds.physical_max,
+                "dmin" => &mut bounds.digital_min,
+                "dmax" => &mut bounds.digital_max,
+                _ => return Err(invalid())
+            };
+            *field = Some(number);
+        }
+        Ok(bounds)
+    }
 
+    fn apply(&self, file_path: &Path, bounds: &mut Bounds) -> Result<(), EdfError> {
+        let overridden: Bounds = Bounds {
+            digital_min: self.digital_min.unwrap_or(bounds.digital_min),
+            digital_max: self.digital_max.unwrap_or(bounds.digital_max),
+            physical_min: self.physical_min.unwrap_or(bounds.physical_min),
+            physical_max: self.physical_max.unwrap_or(bounds.physical_max)
+        };
+        if overridden.physical_min == overridden.physical_max || overridden.digital_min == overridden.digital_max {
+            let message: String = format!("{}: --override-bounds leaves signal '{}' with an empty physical or digital range", &file_path.to_string_lossy(), self.label);
+            return Err(EdfError::InvalidArgument(message));
+        }
+        *bounds = overridden;
+        Ok(())
+    }
 }
 
-impl std::convert::From<csv::Error> for EdfError {
-    fn from(err: csv::Error) -> Self {
-        EdfError::Csv(err.to_string())
+
+#[derive(Clone, Copy, PartialEq)]
+enum TimeColumn {
+    // The sample's date and time, per `datetime_format`.
+    Datetime,
+    // Seconds since the start of the recording.
+    Elapsed,
+    // Whole milliseconds since the Unix epoch, reading the start as UTC.
+    EpochMs,
+    // Whole nanoseconds since the Unix epoch. An i64 covers 1677-09-21 to
+    // 2262-04-11, well past the 1985-2084 an EDF start date can express;
+    // anything outside is written as the missing value.
+    EpochNs
+}
+impl TimeColumn {
+    fn parse(value: &str) -> Result<Self, EdfError> {
+        match value {
+            "datetime" => Ok(TimeColumn::Datetime),
+            "elapsed" => Ok(TimeColumn::Elapsed),
+            "epoch-ms" => Ok(TimeColumn::EpochMs),
+            "epoch-ns" => Ok(TimeColumn::EpochNs),
+            other => Err(EdfError::InvalidArgument(format!("unknown time format '{}'", other)))
+        }
     }
 }
 
-impl std::convert::From<std::io::Error> for EdfError {
-    fn from(err: std::io::Error) -> Self {
-        EdfError::Io(err.to_string())
+
+// What `--on-duplicate-timestamp` does when a row's timestamp isn't later
+// than the one before, e.g. because the sample interval was truncated to 0 ms.
+#[derive(Clone, Copy, PartialEq)]
+enum DuplicateTimestamps {
+    Keep,
+    Error,
+    // Move the row one step of the time format's resolution past the last.
+    Offset
+}
+impl DuplicateTimestamps {
+    fn parse(value: &str) -> Result<Self, EdfError> {
+        match value {
+            "keep" => Ok(DuplicateTimestamps::Keep),
+            "error" => Ok(DuplicateTimestamps::Error),
+            "offset" => Ok(DuplicateTimestamps::Offset),
+            other => Err(EdfError::InvalidArgument(format!("unknown duplicate timestamp policy '{}'", other)))
+        }
     }
 }
 
-impl std::convert::From<std::num::ParseFloatError> for EdfError {
-    fn from(err: std::num::ParseFloatError) -> Self {
-        EdfError::ParseFloat(err.to_string())
+
+// Named sets of defaults for `--profile`; flags given explicitly still win.
+#[derive(Clone, Copy, PartialEq)]
+enum Profile {
+    // ISO timestamps, values converted to µV and checked for a plausible
+    // voltage prefix, missing samples blank.
+    Clinical,
+    // Seconds since the start, raw digital samples, missing samples as NaN.
+    Engineering
+}
+impl Profile {
+    fn parse(value: &str) -> Result<Self, EdfError> {
+        match value {
+            "clinical" => Ok(Profile::Clinical),
+            "engineering" => Ok(Profile::Engineering),
+            other => Err(EdfError::InvalidArgument(format!("unknown profile '{}'", other)))
+        }
+    }
+
+    fn apply(self, options: &mut Options, conversion: &mut ConversionOptionsBuilder) {
+        match self {
+            Profile::Clinical => {
+                options.time_column = TimeColumn::Datetime;
+                options.target_unit = Some("uV".to_string());
+                options.check_units = true;
+                conversion.missing_value("");
+            },
+            Profile::Engineering => {
+                options.time_column = TimeColumn::Elapsed;
+                conversion.raw_digital(true).missing_value("NaN");
+            }
+        }
     }
 }
 
-impl std::convert::From<std::num::ParseIntError> for EdfError {
-    fn from(err: std::num::ParseIntError) -> Self {
-        EdfError::ParseInt(err.to_string())
+
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Csv,
+    Npy,
+    Sqlite,
+    // One `timestamp,signal,value` row per sample, in acquisition order.
+    Replay
+}
+
+
+struct Options {
+    // The settings shared with the library's `edf_to_csv`: delimiter, missing
+    // value marker (text output only; see `missing_cell`), datetime format,
+    // timestamp column name, raw samples, byte order and timeline signal.
+    conversion: ConversionOptions,
+    interpolation: Interpolation,
+    // Longest run of missing samples that interpolation will fill.
+    max_gap: usize,
+    // Text files listing one input path per line.
+    files_from: Vec<PathBuf>,
+    // Print header information instead of converting.
+    list: bool,
+    // Print where each input's output would be written instead of converting.
+    dry_run_list: bool,
+    // Report each file's conversion time and samples per second.
+    verbose: bool,
+    // Write a row per signal instead of a row per sample.
+    transpose: bool,
+    // Add a `sample_offset` column with each row's index within its record.
+    sample_offset_column: bool,
+    // Only write the timestamp on each record's first row; the rest follow
+    // from the sample rate.
+    sparse_timestamps: bool,
+    on_duplicate_timestamp: DuplicateTimestamps,
+    // When fields of the CSV output are quoted. `Never` leaves a field that
+    // holds the delimiter or a line break unreadable.
+    quote_style: QuoteStyle,
+    // Write `<name>.csv.gz` at this level, 0 (stored) to 9 (smallest).
+    gzip: bool,
+    compress_level: u32,
+    // Skip the label and dimension rows.
+    no_header: bool,
+    // Leave out signals that declare zero samples per record.
+    drop_empty_signals: bool,
+    // Report failures as JSON lines on stderr.
+    json_errors: bool,
+    // Rewrite signal labels into valid SQL identifiers.
+    sql_safe_headers: bool,
+    // Flush the output after this many records; 0 only flushes at the end.
+    flush_every: usize,
+    // Write the CSV to a temporary file, fsync it and only then rename it
+    // into place, so the final path never holds a partial file.
+    durable: bool,
+    // Write each data record to its own `<name>.rec<n>.csv`.
+    per_record_files: bool,
+    // Header values repeated in a leading column of every row, by column name.
+    inline_metadata: Vec<(String, MetadataField)>,
+    // Write only the column schema of each file, skipping the samples.
+    schema_only: bool,
+    schema_format: SchemaFormat,
+    // Suffix removed from every label that ends with it, e.g. "-REF".
+    strip_suffix: Option<String>,
+    // Only convert files modified after this moment.
+    since: Option<DateTime<FixedOffset>>,
+    // Only convert files whose name matches one of these, if any are given.
+    include_globs: Vec<Pattern>,
+    // Never convert files whose name matches one of these.
+    exclude_globs: Vec<Pattern>,
+    // Write a `<name>.metadata.json` sidecar next to each CSV.
+    metadata: bool,
+    // Append every file to this one CSV instead of writing one per file.
+    merge_to: Option<PathBuf>,
+    // How often a transient read error is retried before giving up.
+    io_retries: u32,
+    // Compare each file against its existing CSV instead of writing one.
+    verify: bool,
+    // Convert CSVs written by this tool back to EDF files.
+    to_edf: bool,
+    // Write de-identified copies of the EDF files instead of converting them.
+    anonymize: bool,
+    // Convert a generated EDF file and check the result instead.
+    selftest: bool,
+    // With `anonymize`, move the birthdate and start date by this many days.
+    shift_dates: Option<i64>,
+    // Besides the i16::MIN sentinel, treat samples at the declared digital
+    // minimum or maximum as off-scale and leave them blank.
+    offscale_markers: bool,
+    // Order in which the discovered files are processed.
+    sort: SortOrder,
+    // Give up on a file whose conversion takes longer than this.
+    timeout: Option<time::Duration>,
+    // Write the EDF+ annotations to a `<name>.events.csv` next to each CSV.
+    events_csv: bool,
+    // Start date used, with a warning, when a header's date is invalid.
+    fallback_date: Option<NaiveDate>,
+    // Labels whose columns come first, in this order.
+    columns_order: Option<Vec<String>>,
+    // Leave out the signals that `columns_order` doesn't list.
+    drop_unlisted: bool,
+    // Never hold a whole input in memory, not even a ZIP entry or stdin.
+    low_memory: bool,
+    // Warn about voltage dimensions whose range suggests a wrong unit prefix.
+    check_units: bool,
+    // Warn about signals whose dimension is blank.
+    warn_missing_units: bool,
+    // Written as the dimension of signals that have none.
+    unit_placeholder: Option<String>,
+    // Convert every signal's scaled values into this unit, e.g. "uV".
+    target_unit: Option<String>,
+    // Add a third header row with each signal's sample rate in Hz.
+    rate_header: bool,
+    // Write the dimensions to `<name>.units.csv` instead of a header row.
+    units_sidecar: bool,
+    // Only convert the records with these indices, end exclusive.
+    records: Option<(usize, Option<usize>)>,
+    // Stop each file's output after this many data rows, for a quick preview.
+    limit_rows: Option<usize>,
+    // What each file is converted to.
+    format: OutputFormat,
+    // Descend into symlinked directories when expanding a directory input.
+    follow_symlinks: bool,
+    // Signals every file must have; others fail before conversion.
+    expect_schema: Option<ExpectedSchema>,
+    // Number of decimals values are rounded to, and how.
+    precision: Option<usize>,
+    rounding: Rounding,
+    // Write round(value * scale) as an integer instead of the value.
+    fixed_point: Option<f64>,
+    // `old,new` label pairs applied before any other label handling.
+    rename: Vec<(String, String)>,
+    // Read and scale every sample but write nothing, reporting the throughput.
+    no_write: bool,
+    // Scaled values matching any of these are blanked like the sentinel.
+    missing_if: Vec<MissingCondition>,
+    // Threads that format the rows of one file; 1 keeps it sequential.
+    threads_per_file: usize,
+    // Write one CSV per signal instead of one per file.
+    split_signals: bool,
+    // What the timestamp column holds.
+    time_column: TimeColumn,
+    // Only signals sampled at least / at most this often, in Hz.
+    min_rate: Option<f64>,
+    max_rate: Option<f64>,
+    // Records read ahead on a separate thread; 0 reads each one when needed.
+    prefetch: usize,
+    // Only convert every Nth record, reading past the ones in between.
+    record_stride: usize,
+    // Mark whether each event's text is from the EDF+ standard texts.
+    standard_texts: bool,
+    // Write a `<name>.stats.json` summary of each signal's values.
+    stats: bool,
+    // Write a `<name>.coverage.json` with each signal's gaps.
+    coverage: bool,
+    // Calibration bounds replacing the header's, by label after renaming.
+    override_bounds: Vec<BoundsOverride>,
+    // Database that `--format sqlite` writes a table per file into.
+    db: Option<PathBuf>,
+    // Compare where the timestamps end with the duration the header declares,
+    // warning about a drift.
+    validate_timestamps: bool,
+    // Fail the file instead of warning about a timestamp drift or about a
+    // signal that scales to NaN or infinite values.
+    strict: bool,
+    // Write an unreadable record of a local file as blank rows and carry on
+    // at the next record, instead of failing the file.
+    skip_corrupt: bool,
+    // Decimal separator of the values, independent of the delimiter.
+    decimal: char
+}
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            conversion: ConversionOptions::default(),
+            interpolation: Interpolation::None,
+            max_gap: 1,
+            files_from: Vec::new(),
+            list: false,
+            dry_run_list: false,
+            verbose: false,
+            transpose: false,
+            sample_offset_column: false,
+            sparse_timestamps: false,
+            on_duplicate_timestamp: DuplicateTimestamps::Keep,
+            quote_style: QuoteStyle::Necessary,
+            gzip: false,
+            compress_level: 6,
+            no_header: false,
+            drop_empty_signals: false,
+            json_errors: false,
+            sql_safe_headers: false,
+            flush_every: 100,
+            durable: false,
+            per_record_files: false,
+            inline_metadata: Vec::new(),
+            schema_only: false,
+            schema_format: SchemaFormat::Json,
+            strip_suffix: None,
+            since: None,
+            include_globs: Vec::new(),
+            exclude_globs: Vec::new(),
+            metadata: false,
+            merge_to: None,
+            io_retries: 0,
+            verify: false,
+            to_edf: false,
+            anonymize: false,
+            selftest: false,
+            shift_dates: None,
+            offscale_markers: false,
+            sort: SortOrder::Path,
+            timeout: None,
+            events_csv: false,
+            fallback_date: None,
+            columns_order: None,
+            drop_unlisted: false,
+            low_memory: false,
+            check_units: false,
+            warn_missing_units: false,
+            unit_placeholder: None,
+            target_unit: None,
+            rate_header: false,
+            units_sidecar: false,
+            records: None,
+            limit_rows: None,
+            format: OutputFormat::Csv,
+            follow_symlinks: false,
+            expect_schema: None,
+            precision: None,
+            rounding: Rounding::HalfEven,
+            fixed_point: None,
+            rename: Vec::new(),
+            no_write: false,
+            missing_if: Vec::new(),
+            threads_per_file: 1,
+            split_signals: false,
+            time_column: TimeColumn::Datetime,
+            min_rate: None,
+            max_rate: None,
+            prefetch: 0,
+            record_stride: 1,
+            standard_texts: false,
+            stats: false,
+            coverage: false,
+            override_bounds: Vec::new(),
+            db: None,
+            validate_timestamps: false,
+            strict: false,
+            skip_corrupt: false,
+            decimal: '.'
+        }
     }
 }
 
 
-fn get_start_date(reader: &mut BufReader<File>) -> Result<LocalDate, EdfError> {
-    let skip_bytes: i64 = 168;
-    reader.by_ref().seek_relative(skip_bytes)?;
+// One line of `--json-errors` output.
+#[derive(Serialize)]
+struct ErrorReport<'a> {
+    file: String,
+    message: String,
+    #[serde(flatten)]
+    error: &'a EdfError
+}
+impl<'a> ErrorReport<'a> {
+    fn new(file_path: &Path, error: &'a EdfError) -> Self {
+        ErrorReport {
+            file: file_path.to_string_lossy().to_string(),
+            message: error.to_string(),
+            error
+        }
+    }
+}
 
-    let mut day_string = String::with_capacity(2);
-    reader.by_ref().take(2).read_to_string(&mut day_string)?;
-    let day: i8 = day_string.parse()?;
+// Reads the header and applies everything that adjusts or checks its signals
+// before conversion, reporting the header's warnings.
+fn read_prepared_header(file_path: &Path, reader: &mut EdfReader, options: &Options) -> Result<EdfHeader, EdfError> {
+    let mut header: EdfHeader = read_edf_header(reader, options)?;
+    for warning in &header.warnings {
+        warn(file_path, warning);
+    }
+    process_labels(file_path, &mut header.signals, options);
+    for bounds_override in &options.override_bounds {
+        let mut found: bool = false;
+        for signal in header.signals.iter_mut().filter(|s| s.label == bounds_override.label) {
+            bounds_override.apply(file_path, &mut signal.bounds)?;
+            found = true;
+        }
+        if !found {
+            warn(file_path, &format!("signal '{}' from --override-bounds isn't in the file", bounds_override.label));
+        }
+    }
+    if options.drop_empty_signals {
+        header.signals.retain(|s| s.num_samples > 0);
+    }
+    check_scaling(file_path, &header.signals, options)?;
+    if let Some(expected) = &options.expect_schema {
+        check_expected_schema(file_path, &header, expected)?;
+    }
+    Ok(header)
+}
 
-    reader.by_ref().seek_relative(1)?;
 
-    let mut month_string = String::with_capacity(2);
-    reader.by_ref().take(2).read_to_string(&mut month_string)?;
-    let month: Month = match Month::from_one(month_string.parse::<i8>()?) {
-        Ok(month) => month,
-        Err(e) => return Err(EdfError::Datetime(e.to_string()))
+// The records `--records` selects (all by default), with the reader moved
+// past the ones before them. With `--skip-corrupt`, records a truncated local
+// file doesn't hold completely are left out instead of failing the file.
+fn skip_to_records(
+    file_path: &Path, reader: &mut EdfReader, num_records: usize, data_offset: u64, record_size: usize, options: &Options
+) -> Result<Range<usize>, EdfError> {
+    let (first, mut end): (usize, usize) = match options.records {
+        Some((first, last)) => (first, last.unwrap_or(num_records)),
+        None => (0, num_records)
     };
+    if options.records.is_some() && (first >= end || end > num_records) {
+        let message: String = format!("{}: records {}..{} are out of range, the file has {} records", &file_path.to_string_lossy(), first, end, num_records);
+        return Err(EdfError::InvalidArgument(message));
+    }
+    if options.skip_corrupt && is_local_file(file_path) {
+        let file_size: u64 = fs::metadata(file_path)?.len();
+        let complete: usize = (file_size.saturating_sub(data_offset) / (record_size as u64 * 2).max(1)) as usize;
+        if complete < end {
+            warn(file_path, &format!("the file ends after {} complete records of the {} declared; the rest is left out", complete, num_records));
+            end = complete.max(first);
+        }
+    }
+    // Records have a fixed size, so the skipped ones are seeked or read past
+    // unparsed.
+    let skipped_bytes: u64 = (first * record_size * 2) as u64;
+    if !skip_input(reader, skipped_bytes)? {
+        return Err(EdfError::Io(format!("{}: the file ends before record {}", &file_path.to_string_lossy(), first)));
+    }
+    Ok(first..end)
+}
 
-    reader.by_ref().seek_relative(1)?;
 
-    let mut year_string = String::with_capacity(2);
-    reader.by_ref().take(2).read_to_string(&mut year_string)?;
-    let year: i64 = 2000 + year_string.parse::<i64>()?;
+// Reads the next record's samples. With `--skip-corrupt`, a record of a local
+// file that can't be read comes back blank, and reading resumes from a fresh
+// handle at the next record boundary, which the fixed record size gives.
+fn read_next_record(
+    source: &mut RecordSource, file_path: &Path, record: usize, records_end: usize, data_offset: u64, record_size: usize, options: &Options
+) -> Result<Vec<i16>, EdfError> {
+    let error: EdfError = match source.next_record() {
+        Ok(values) => return Ok(values),
+        Err(error) if options.skip_corrupt && is_local_file(file_path) => error,
+        Err(error) => return Err(error)
+    };
+    warn(file_path, &format!("record {} can't be read ({:?}); writing it blank", record, error));
+    let mut file: File = File::open(file_path)?;
+    file.seek(SeekFrom::Start(data_offset + ((record + 1) * record_size * 2) as u64))?;
+    let reader: EdfReader = BufReader::new(RetryReader::new(EdfSource::File(file), options.io_retries));
+    *source = RecordSource::new(reader, record_size, records_end - record - 1, options);
+    Ok(vec![i16::MIN; record_size])
+}
+
 
-    match LocalDate::ymd(year, month, day) {
-        Ok(date) => Ok(date),
-        Err(e) => Err(EdfError::Datetime(e.to_string()))
+// Scales one signal's part of a record and applies the options that blank
+// samples. Interpolation waits for the neighbouring records, in `GapFiller`.
+fn scale_signal(signal: &Signal, raw_values: &[i16], options: &Options) -> Vec<Option<f64>> {
+    let mut signal_values: Vec<Option<f64>> = options.conversion.scale_samples(signal, raw_values);
+    if options.offscale_markers {
+        for (value, &raw) in signal_values.iter_mut().zip(raw_values) {
+            if signal.bounds.is_off_scale(raw) {
+                *value = None;
+            }
+        }
+    }
+    if !options.missing_if.is_empty() {
+        for value in signal_values.iter_mut() {
+            if value.is_some_and(|v| options.missing_if.iter().any(|condition| condition.matches(v))) {
+                *value = None;
+            }
+        }
     }
+    signal_values
 }
 
 
-fn get_start_time(reader: &mut BufReader<File>) -> Result<LocalTime, EdfError> {
-    let mut hour_string = String::with_capacity(2);
-    reader.by_ref().take(2).read_to_string(&mut hour_string)?;
-    let hour: i8 = hour_string.parse()?;
+// NaN or infinite results, e.g. from a zero digital range, are written as
+// missing rather than as "NaN" or "inf". `check_scaling` has already warned
+// about them, or failed the file with `--strict`.
+fn blank_non_finite(values: &mut [Option<f64>]) {
+    for value in values.iter_mut() {
+        if value.is_some_and(|v| !v.is_finite()) {
+            *value = None;
+        }
+    }
+}
+
 
-    reader.by_ref().seek_relative(1)?; 
+// Warns about signals whose bounds can't give finite physical values, or
+// fails the file with `--strict`.
+fn check_scaling(file_path: &Path, signals: &[Signal], options: &Options) -> Result<(), EdfError> {
+    if options.conversion.raw_digital() {
+        return Ok(());
+    }
+    for signal in signals.iter().filter(|s| !s.is_annotation() && s.num_samples > 0) {
+        let bounds: &Bounds = &signal.bounds;
+        let finite: bool = [bounds.digital_min, bounds.digital_max, bounds.physical_min, bounds.physical_max].iter().all(|v| v.is_finite())
+            && bounds.digital_max != bounds.digital_min;
+        if finite {
+            continue;
+        }
+        let message: String = format!(
+            "signal '{}' can't be scaled with digital range {}..{} and physical range {}..{}",
+            signal.label, bounds.digital_min, bounds.digital_max, bounds.physical_min, bounds.physical_max
+        );
+        if options.strict {
+            return Err(EdfError::NonFinite(format!("{}: {}", &file_path.to_string_lossy(), message)));
+        }
+        warn(file_path, &format!("{}; its values are written as missing", message));
+    }
+    Ok(())
+}
 
-    let mut minute_string = String::with_capacity(2);
-    reader.by_ref().take(2).read_to_string(&mut minute_string)?;
-    let minute: i8 = minute_string.parse()?;
 
-    reader.by_ref().seek_relative(1)?; 
+fn check_byte_order(file_path: &Path, signal: &Signal, raw_values: &[i16], options: &Options) {
+    if looks_byte_swapped(raw_values) {
+        let other: &str = if options.conversion.endian() == Endian::Little { "big" } else { "little" };
+        warn(file_path, &format!("signal '{}' looks byte-swapped; the samples may be {}-endian (see --endian)", signal.label, other));
+    }
+}
 
-    let mut second_string = String::with_capacity(2);
-    reader.by_ref().take(2).read_to_string(&mut second_string)?;
-    let second: i8 = second_string.parse()?;
 
-    match LocalTime::hms(hour, minute, second) {
-        Ok(time) => Ok(time),
-        Err(e) => Err(EdfError::Datetime(e.to_string()))
+// Contents of the timestamp column for a sample taken at `timestamp` in a
+// recording that started at `start`.
+fn format_timestamp(timestamp: NaiveDateTime, start: NaiveDateTime, options: &Options) -> String {
+    match options.time_column {
+        TimeColumn::Datetime => timestamp.format(options.conversion.datetime_format()).to_string(),
+        TimeColumn::Elapsed => localize_decimal(format!("{:.3}", (timestamp - start).num_milliseconds() as f64 / 1000.0), options),
+        TimeColumn::EpochMs => timestamp.and_utc().timestamp_millis().to_string(),
+        TimeColumn::EpochNs => match timestamp.and_utc().timestamp_nanos_opt() {
+            Some(nanos) => nanos.to_string(),
+            None => missing_cell(options)
+        }
     }
+}
 
+
+// The cell for a missing sample (the i16::MIN sentinel, a blanked value or
+// a timestamp out of range), by output format. Text output gets the
+// `--missing-value` marker, empty unless set. The `.npy` and SQLite sinks
+// get an empty cell and store NaN and NULL for it, so a real value that
+// happens to equal the marker can't turn into a missing one.
+fn missing_cell(options: &Options) -> String {
+    match options.format {
+        OutputFormat::Csv | OutputFormat::Replay => options.conversion.missing_value().to_string(),
+        OutputFormat::Npy | OutputFormat::Sqlite => String::new()
+    }
 }
 
 
-fn get_num_records(reader: &mut BufReader<File>) -> Result<usize , EdfError> {
-    let skip_bytes: i64 = 52;
-    reader.by_ref().seek_relative(skip_bytes)?;
+// What the second header row says about the timestamp column.
+fn timestamp_unit(options: &Options) -> &str {
+    match options.time_column {
+        TimeColumn::Elapsed => "s",
+        TimeColumn::EpochMs => "ms since 1970-01-01",
+        TimeColumn::EpochNs => "ns since 1970-01-01",
+        TimeColumn::Datetime => options.conversion.timestamp_unit()
+    }
+}
+
 
-    let mut num_records: String = String::with_capacity(8);
-    reader.by_ref().take(8).read_to_string(&mut num_records)?;
-    Ok(num_records.trim().parse()?)
+// Applies `--on-duplicate-timestamp` to a row's timestamp, given the last one
+// written. Offsets step by a millisecond, or a nanosecond with epoch-ns; a
+// `--datetime-format` coarser than that can still show repeats.
+fn dedup_timestamp(file_path: &Path, timestamp: NaiveDateTime, last: &mut Option<NaiveDateTime>, options: &Options) -> Result<NaiveDateTime, EdfError> {
+    let mut timestamp: NaiveDateTime = timestamp;
+    if let Some(previous) = *last {
+        if timestamp <= previous {
+            match options.on_duplicate_timestamp {
+                DuplicateTimestamps::Keep => {},
+                DuplicateTimestamps::Error => {
+                    let message: String = format!("{}: more than one row at {}", &file_path.to_string_lossy(), timestamp.format(DEFAULT_DATETIME_FORMAT));
+                    return Err(EdfError::DuplicateTimestamp(message));
+                },
+                DuplicateTimestamps::Offset => {
+                    let step: Duration = if options.time_column == TimeColumn::EpochNs { Duration::nanoseconds(1) } else { Duration::milliseconds(1) };
+                    timestamp = previous + step;
+                }
+            }
+        }
+    }
+    *last = Some(timestamp);
+    Ok(timestamp)
 }
 
 
-fn get_record_duration(reader: &mut BufReader<File>) -> Result<usize , EdfError> {
-    let mut record_duration: String = String::with_capacity(8);
-    reader.by_ref().take(8).read_to_string(&mut record_duration)?;
-    Ok(record_duration.trim().parse()?)
+// How far after the start the rows of `first_record` begin on `timeline`.
+fn records_offset(file_path: &Path, timeline: &Timeline, first_record: usize) -> Result<Duration, EdfError> {
+    match timeline.record_offset(first_record) {
+        Some(offset) => Ok(offset),
+        None => Err(EdfError::Datetime(format!("{}: record {} starts too far from the recording start for a timestamp", &file_path.to_string_lossy(), first_record)))
+    }
 }
 
 
-fn get_num_signals(reader: &mut BufReader<File>) -> Result<usize , EdfError> {
-    let mut num_signals: String = String::with_capacity(4);
-    reader.by_ref().take(4).read_to_string(&mut num_signals)?;
-    Ok(num_signals.trim().parse()?)
+fn format_value(value: f64, options: &Options) -> String {
+    if let Some(scale) = options.fixed_point {
+        return fixed_point(value, scale, options.rounding);
+    }
+    let formatted: String = match options.precision {
+        Some(precision) => round_decimal(&value.to_string(), precision, options.rounding),
+        None => value.to_string()
+    };
+    localize_decimal(formatted, options)
 }
 
 
-fn get_signals(reader: &mut BufReader<File>, num_signals: usize) -> Result<Vec<Signal>, EdfError> {
-    let header_signal_bytes: [u64; 10] = [16, 80, 8, 8, 8, 8, 8, 80, 8, 32];
-    let skip_indices: [usize; 3] = [1, 7, 9];
+// `f64` formatting never uses an exponent, so the only '.' in a formatted
+// number is the decimal point.
+fn localize_decimal(formatted: String, options: &Options) -> String {
+    if options.decimal == '.' {
+        return formatted;
+    }
+    formatted.replace('.', &options.decimal.to_string())
+}
+
 
-    let s: Vec<String> = Vec::with_capacity(7);
-    let mut signals_vec: Vec<Vec<String>> = vec![s; num_signals];
-    
-    for (i, bytes) in header_signal_bytes.iter().enumerate() {
-        let bytes: u64 = *bytes;
-        for s in signals_vec.iter_mut() {
-            if skip_indices.contains(&i) {
-                reader.by_ref().seek_relative(bytes as i64)?;
+// A CSV writer with the `--delimiter` in effect.
+fn csv_writer(csv_path: &Path, options: &Options) -> csv::Result<Writer<File>> {
+    WriterBuilder::new().delimiter(options.conversion.delimiter()).quote_style(options.quote_style).from_path(csv_path)
+}
+
+
+// Rounds the shortest decimal form of a value, i.e. the digits as printed, so
+// that "2.675" rounds half up to "2.68" even though the nearest f64 is a bit
+// below it. `format!` would round that binary value half to even instead.
+fn round_decimal(value: &str, precision: usize, rounding: Rounding) -> String {
+    let (negative, digits): (bool, &str) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value)
+    };
+    let (integer, fraction): (&str, &str) = digits.split_once('.').unwrap_or((digits, ""));
+    let mut kept: Vec<u8> = integer.bytes().chain(fraction.bytes().chain(std::iter::repeat(b'0')).take(precision)).collect();
+    let rest: &[u8] = fraction.as_bytes().get(precision..).unwrap_or_default();
+
+    let round_up: bool = match (rounding, rest.first()) {
+        (_, None) | (Rounding::Truncate, _) => false,
+        (Rounding::HalfUp, Some(&first)) => first >= b'5',
+        (Rounding::HalfEven, Some(&first)) => {
+            let tie: bool = first == b'5' && rest[1..].iter().all(|&d| d == b'0');
+            let last_odd: bool = kept.last().is_some_and(|&d| (d - b'0') % 2 == 1);
+            first > b'5' || (first == b'5' && (!tie || last_odd))
+        }
+    };
+    if round_up {
+        let mut position: usize = kept.len();
+        loop {
+            if position == 0 {
+                kept.insert(0, b'1');
+                break;
+            }
+            position -= 1;
+            if kept[position] == b'9' {
+                kept[position] = b'0';
             } else {
-                let mut buf = String::with_capacity(bytes as usize);
-                reader.by_ref().take(bytes).read_to_string(&mut buf)?;
-                s.push(buf.trim().to_string());
+                kept[position] += 1;
+                break;
             }
         }
     }
-    
-    
-    let mut signals: Vec<Signal> = Vec::with_capacity(num_signals);
-    for s in signals_vec {
-        let num_samples: usize = s[6].parse()?;
-        signals.push(Signal {
-            label: s[0].clone(),
-            dimension: s[1].clone(),
-            bounds: Bounds {
-                physical_min: s[2].parse()?,
-                physical_max: s[3].parse()?,
-                digital_min: s[4].parse()?,
-                digital_max: s[5].parse()?
-            },
-            num_samples
-        })
+
+    let split: usize = kept.len() - precision;
+    let mut rounded: String = String::with_capacity(kept.len() + 2);
+    // A value that rounds to zero loses its sign.
+    if negative && kept.iter().any(|&d| d != b'0') {
+        rounded.push('-');
+    }
+    rounded.push_str(std::str::from_utf8(&kept[..split]).unwrap());
+    if precision > 0 {
+        rounded.push('.');
+        rounded.push_str(std::str::from_utf8(&kept[split..]).unwrap());
+    }
+    rounded
+}
+
+
+// `--fixed-point`: the value times `scale`, rounded to an integer with
+// `--rounding`. A power-of-ten scale shifts the decimal point of the value's
+// shortest representation instead of multiplying, so 1.0005 at scale 1000
+// rounds from exactly 1000.5 rather than from 1000.4999999999999.
+fn fixed_point(value: f64, scale: f64, rounding: Rounding) -> String {
+    let exponent: i32 = scale.log10().round() as i32;
+    if exponent < 0 || 10f64.powi(exponent) != scale {
+        return round_decimal(&(value * scale).to_string(), 0, rounding);
+    }
+    let shifted: String = round_decimal(&value.to_string(), exponent as usize, rounding).replace('.', "");
+    let (sign, digits): (&str, &str) = match shifted.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", shifted.as_str())
+    };
+    match digits.trim_start_matches('0') {
+        "" => "0".to_string(),
+        digits => format!("{}{}", sign, digits)
     }
+}
 
-    Ok(signals)
+
+// `--interpolate linear`: fills runs of missing samples no longer than
+// `max_gap` with a line between the valid samples on either side, also when
+// the run crosses into later records. Records come out in order once none of
+// their signals ends in a gap that may still be filled, so a signal holds
+// back at most `max_gap` samples. Runs at the start or end of the converted
+// records, or around records skipped by `--record-stride`, have only one
+// neighbour and stay missing.
+struct GapFiller {
+    linear: bool,
+    max_gap: usize,
+    queue: VecDeque<PendingRecord>,
+    // Records already returned, which numbers the queue's entries.
+    released: usize,
+    previous_record: Option<usize>,
+    // Per signal: the last valid value, and the open run of missing samples
+    // after it as (entry, sample) where it starts and its length.
+    last_valid: Vec<Option<f64>>,
+    gaps: Vec<Option<((usize, usize), usize)>>
 }
+impl GapFiller {
+    fn new(num_signals: usize, options: &Options) -> Self {
+        GapFiller {
+            linear: options.interpolation == Interpolation::Linear,
+            max_gap: options.max_gap,
+            queue: VecDeque::new(),
+            released: 0,
+            previous_record: None,
+            last_valid: vec![None; num_signals],
+            gaps: vec![None; num_signals]
+        }
+    }
 
+    // Takes a record's scaled samples and returns the records that are done.
+    fn push(&mut self, record: usize, values: Vec<i16>, scaled: Vec<Vec<Option<f64>>>) -> Vec<PendingRecord> {
+        if !self.linear {
+            return vec![(record, values, scaled)];
+        }
+        if self.previous_record.is_some_and(|previous| previous + 1 != record) {
+            self.last_valid.fill(None);
+            self.gaps.fill(None);
+        }
+        self.previous_record = Some(record);
+        self.queue.push_back((record, values, scaled));
+        let entry: usize = self.released + self.queue.len() - 1;
+        for signal in 0..self.gaps.len() {
+            for i in 0..self.queue.back().unwrap().2[signal].len() {
+                match self.queue.back().unwrap().2[signal][i] {
+                    Some(right) => {
+                        if let (Some(left), Some((start, len))) = (self.last_valid[signal], self.gaps[signal]) {
+                            self.fill(signal, start, len, left, right);
+                        }
+                        self.gaps[signal] = None;
+                        self.last_valid[signal] = Some(right);
+                    },
+                    None if self.last_valid[signal].is_none() => {},
+                    None => match &mut self.gaps[signal] {
+                        Some((_, len)) if *len == self.max_gap => {
+                            self.gaps[signal] = None;
+                            self.last_valid[signal] = None;
+                        },
+                        Some((_, len)) => *len += 1,
+                        None if self.max_gap == 0 => self.last_valid[signal] = None,
+                        None => self.gaps[signal] = Some(((entry, i), 1))
+                    }
+                }
+            }
+        }
+        let mut done: Vec<PendingRecord> = Vec::new();
+        while !self.queue.is_empty() && self.gaps.iter().flatten().all(|&((start, _), _)| start > self.released) {
+            done.push(self.queue.pop_front().unwrap());
+            self.released += 1;
+        }
+        done
+    }
 
-fn read_record_samples(reader: &mut BufReader<File>, num_signals: usize, num_samples: usize) -> Result<Vec<i16>, EdfError> {
-    let capacity: usize = num_signals * num_samples;
-    let mut values: Vec<i16> = Vec::with_capacity(capacity);
-    for _ in 0..capacity {
-        let value: i16 = reader.by_ref().read_i16::<LittleEndian>()?;
-        values.push(value);
+    // The records still held back, with their open runs left missing.
+    fn finish(&mut self) -> Vec<PendingRecord> {
+        self.released += self.queue.len();
+        self.gaps.fill(None);
+        self.queue.drain(..).collect()
     }
-    Ok(values)
+
+    fn fill(&mut self, signal: usize, (entry, sample): (usize, usize), len: usize, left: f64, right: f64) {
+        let step: f64 = (right - left) / (len + 1) as f64;
+        let (mut entry, mut sample): (usize, usize) = (entry - self.released, sample);
+        for k in 1..=len {
+            while sample >= self.queue[entry].2[signal].len() {
+                entry += 1;
+                sample = 0;
+            }
+            self.queue[entry].2[signal][sample] = Some(left + step * k as f64);
+            sample += 1;
+        }
+    }
+}
+
+
+// Turns labels into unique SQL identifiers: anything but ASCII letters, digits
+// and underscores becomes an underscore, a leading digit gets an underscore
+// prefix, and repeated names, or the time column's, get a numeric suffix.
+fn sql_safe_labels(labels: &[String], timestamp_column: &str) -> Vec<String> {
+    let mut taken: Vec<String> = vec![timestamp_column.to_string()];
+    let mut safe_labels: Vec<String> = Vec::with_capacity(labels.len());
+
+    for label in labels {
+        let mut safe: String = label
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        if safe.is_empty() {
+            safe.push_str("signal");
+        }
+        if safe.starts_with(|c: char| c.is_ascii_digit()) {
+            safe.insert(0, '_');
+        }
+
+        let mut unique: String = safe.clone();
+        let mut suffix: usize = 2;
+        while taken.iter().any(|t| t.eq_ignore_ascii_case(&unique)) {
+            unique = format!("{}_{}", safe, suffix);
+            suffix += 1;
+        }
+        taken.push(unique.clone());
+        safe_labels.push(unique);
+    }
+    safe_labels
 }
 
-fn increment_timestamp(mut timestamp: Instant, interval: Duration) -> Instant {
-    timestamp = timestamp + interval;
-    if timestamp.milliseconds() >= 1000 {
-        let seconds: i64 = timestamp.seconds() + (timestamp.milliseconds() / 1000) as i64;
-        let milliseconds = timestamp.milliseconds() % 1000;
-        timestamp = Instant::at_ms(seconds, milliseconds);
+
+// Applies the label options to the parsed signals and warns about labels that
+// are still duplicated afterwards.
+// Labels are renamed first, so the map uses the labels as they are in the
+// file; `--strip-suffix` and every option naming signals (`--columns-order`,
+// `--timeline-signal`, `--expect-schema`) then see the new names.
+fn process_labels(file_path: &Path, signals: &mut [Signal], options: &Options) {
+    for signal in signals.iter_mut().filter(|s| !s.is_annotation()) {
+        if let Some((_, new)) = options.rename.iter().find(|(old, _)| *old == signal.label) {
+            signal.label = new.clone();
+        }
+    }
+    if let Some(suffix) = &options.strip_suffix {
+        for signal in signals.iter_mut().filter(|s| !s.is_annotation()) {
+            if let Some(stripped) = signal.label.strip_suffix(suffix.as_str()) {
+                signal.label = stripped.trim_end().to_string();
+            }
+        }
+    }
+
+    // Repeated annotation signals are allowed and never become columns.
+    let mut reported: Vec<&str> = Vec::new();
+    for signal in signals.iter().filter(|s| !s.is_annotation()) {
+        let count: usize = signals.iter().filter(|s| s.label == signal.label).count();
+        if count > 1 && !reported.contains(&signal.label.as_str()) {
+            warn(file_path, &format!("label '{}' is used by {} signals", signal.label, count));
+            reported.push(&signal.label);
+        }
     }
-    timestamp
 }
 
-fn parse_edf(file_path: &mut PathBuf, target_dir: &Path) -> Result<(), EdfError> {
-    let f: File = File::open(&file_path)?;
-    let mut reader: BufReader<File> = BufReader::new(f);
 
-    let date: LocalDate = get_start_date(&mut reader)?;
-    let time: LocalTime = get_start_time(&mut reader)?;
-    let mut timestamp: Instant = LocalDateTime::new(date, time).to_instant();
+// The error for signals that can't share a row per sample: lists every
+// signal's samples per record, marks the ones off the most common count
+// (often a status or low-rate channel) and suggests how to get past it.
+fn mismatched_signals(file_path: &Path, columns: &[&Signal], remedy: &str) -> EdfError {
+    let counts: Vec<usize> = columns.iter().map(|s| s.num_samples).filter(|&n| n > 0).collect();
+    let common: usize = counts.iter().copied().max_by_key(|&n| counts.iter().filter(|&&other| other == n).count()).unwrap_or(0);
+    let listing: Vec<String> = columns.iter().map(|s| {
+        let odd: &str = if s.num_samples > 0 && s.num_samples != common { " (odd one out)" } else { "" };
+        format!("'{}' {}{}", s.label, s.num_samples, odd)
+    }).collect();
+    let odd_labels: Vec<&str> = columns.iter().filter(|s| s.num_samples > 0 && s.num_samples != common).map(|s| s.label.as_str()).collect();
+    let kept_labels: Vec<&str> = columns.iter().filter(|s| s.num_samples == common).map(|s| s.label.as_str()).collect();
+    let message: String = format!(
+        "{}: samples per record are {}. Leave out {} with --columns-order \"{}\" --drop-unlisted, {}",
+        file_path.to_string_lossy(), listing.join(", "), odd_labels.join(", "), kept_labels.join(","), remedy
+    );
+    EdfError::MismatchedSignals(message)
+}
 
-    let num_records: usize = get_num_records(&mut reader)?;
-    let record_duration: usize = get_record_duration(&mut reader)?;
-    let num_signals: usize = get_num_signals(&mut reader)?;
-    let signals: Vec<Signal> = get_signals(&mut reader, num_signals)?;
 
-    let num_samples:usize = signals[0].num_samples;
-    if !&signals.iter().skip(1).map(|s| s.num_samples).all(|n| n == num_samples) {
-        let message: String = format!("{}: Not all signals have the same number of samples per record!", &file_path.to_string_lossy());
-        return Err(EdfError::MismatchedSignals(message));
+// Indices into `signals` in the order their columns are written: the listed
+// labels first, then (unless dropped) the rest in header order. Signals
+// outside `--min-rate`/`--max-rate` are left out, even listed ones.
+fn column_order(file_path: &Path, signals: &[&Signal], record_duration: f64, options: &Options) -> Vec<usize> {
+    let mut order: Vec<usize> = listed_order(file_path, signals, options);
+    order.retain(|&index| {
+        let rate: f64 = signals[index].sample_rate(record_duration);
+        options.min_rate.is_none_or(|min_rate| rate >= min_rate) && options.max_rate.is_none_or(|max_rate| rate <= max_rate)
+    });
+    if order.is_empty() && !signals.is_empty() {
+        warn(file_path, "no signal is within the --min-rate/--max-rate range");
     }
+    order
+}
 
-    let interval_ms: i16 = (1000.0 * record_duration as f32 / num_samples as f32) as i16;
-    let sample_interval: Duration = Duration::of_ms((&interval_ms / 1000) as i64, &interval_ms % 1000);
 
-    file_path.set_extension("csv");
-    let target_file: &Path = Path::new(file_path.file_name().unwrap());
-    let target_path: PathBuf = target_dir.join(target_file);
+fn listed_order(file_path: &Path, signals: &[&Signal], options: &Options) -> Vec<usize> {
+    let Some(columns_order) = &options.columns_order else {
+        return (0..signals.len()).collect();
+    };
+    let mut order: Vec<usize> = Vec::with_capacity(signals.len());
+    for label in columns_order {
+        let matching: Vec<usize> = (0..signals.len())
+            .filter(|&index| signals[index].label == *label && !order.contains(&index))
+            .collect();
+        if matching.is_empty() {
+            warn(file_path, &format!("signal '{}' from --columns-order isn't in the file", label));
+        }
+        order.extend(matching);
+    }
+    if !options.drop_unlisted {
+        let unlisted: Vec<usize> = (0..signals.len()).filter(|index| !order.contains(index)).collect();
+        order.extend(unlisted);
+    }
+    order
+}
 
-    let mut writer: Writer<File> = Writer::from_path(target_path)?;
-    let mut row: Vec<String> = Vec::with_capacity(1 + num_signals);
 
-    row.push("timestamp".to_string());
-    for signal in &signals {
-        row.push(signal.label.clone());
+// EDF files inside a ZIP archive are addressed as `archive.zip/inner/path.edf`.
+// Returns the archive and the path of the entry within it.
+fn split_zip_path(file_path: &Path) -> Option<(&Path, String)> {
+    if file_path.is_file() {
+        return None;
     }
-    writer.write_record(&row)?;
-    row.clear();
+    let archive: &Path = file_path.ancestors().skip(1).find(|ancestor| is_zip_file(ancestor))?;
+    let entry: Vec<String> = file_path
+        .strip_prefix(archive)
+        .ok()?
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect();
+    Some((archive, entry.join("/")))
+}
 
-    row.push("YYYY-MM-DD hh:mm:ss".to_string());
-    for signal in &signals {
-        row.push(signal.dimension.clone());
+
+// ZIP entries can't be read independently of their archive, so an entry is
+// decompressed into memory to give the parser a seekable reader, or with
+// `--low-memory` streamed from a decompressing thread. Stdin is likewise
+// buffered whole unless `--low-memory` is given.
+fn open_edf(file_path: &Path, options: &Options) -> Result<EdfReader, EdfError> {
+    if file_path == Path::new(STDIN_PATH) && options.low_memory {
+        if STDIN_STREAMED.swap(true, Ordering::Relaxed) {
+            return Err(EdfError::Io("stdin was already read, and with --low-memory it isn't kept to be read again".to_string()));
+        }
+        return Ok(BufReader::new(RetryReader::new(EdfSource::Stream(Box::new(io::stdin())), options.io_retries)));
+    }
+    if file_path == Path::new(STDIN_PATH) {
+        let contents: &'static [u8] = match STDIN_CONTENTS.get_or_init(read_stdin) {
+            Ok(contents) => contents,
+            Err(e) => return Err(EdfError::Io(e.clone()))
+        };
+        return Ok(BufReader::new(RetryReader::new(EdfSource::Memory(Cursor::new(Cow::Borrowed(contents))), options.io_retries)));
     }
-    writer.write_record(&row)?;
+    // A URL is streamed with a single GET, so reading only the header, as
+    // `--list` does, doesn't download the rest of the file.
+    if is_url(file_path) {
+        let response: ureq::http::Response<ureq::Body> = ureq::get(file_path.to_string_lossy().as_ref()).call().map_err(|e| EdfError::Http(e.to_string()))?;
+        let body: Box<dyn Read + Send> = Box::new(response.into_body().into_reader());
+        return Ok(BufReader::new(RetryReader::new(EdfSource::Stream(body), options.io_retries)));
+    }
+    let source: EdfSource = match split_zip_path(file_path) {
+        Some((archive_path, entry_name)) if options.low_memory => {
+            EdfSource::Stream(Box::new(ZipEntryReader::open(archive_path, entry_name)?))
+        },
+        Some((archive_path, entry_name)) => {
+            let mut archive: ZipArchive<File> = ZipArchive::new(File::open(archive_path)?)?;
+            let mut entry = archive.by_name(&entry_name)?;
+            let mut contents: Vec<u8> = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut contents)?;
+            EdfSource::Memory(Cursor::new(Cow::Owned(contents)))
+        },
+        None => EdfSource::File(File::open(file_path)?)
+    };
+    Ok(BufReader::new(RetryReader::new(source, options.io_retries)))
+}
 
-    for _ in 0..num_records {
-        let values: Vec<i16> = read_record_samples(&mut reader, num_signals, num_samples)?;
-        for i in 0..num_samples {
-            row.clear();
-            row.push(LocalDateTime::from_instant(timestamp).iso().to_string());
 
-            for j in 0..num_signals {
-                let val: &i16 = &values[i + j * num_samples];
-                let cleaned_val: String = match signals[j].bounds.scale(val) {
-                    Some(scaled) => scaled.to_string(),
-                    None => "".to_string()
-                };
-                row.push(cleaned_val);
+// Yields the raw samples of each record to convert. With `--prefetch N` a
+// reader thread stays up to N records ahead, so reading overlaps with
+// scaling and writing; records still arrive in file order. With
+// `--record-stride N`, `num_records` counts the records yielded and the N - 1
+// after each one are skipped unparsed.
+enum RecordSource {
+    Direct {reader: EdfReader, record_size: usize, endian: Endian, stride: usize},
+    Prefetched(mpsc::Receiver<Result<Vec<i16>, EdfError>>)
+}
+impl RecordSource {
+    fn new(mut reader: EdfReader, record_size: usize, num_records: usize, options: &Options) -> Self {
+        let endian: Endian = options.conversion.endian();
+        let stride: usize = options.record_stride;
+        if options.prefetch == 0 {
+            return RecordSource::Direct {reader, record_size, endian, stride};
+        }
+        let (sender, receiver) = mpsc::sync_channel(options.prefetch);
+        thread::spawn(move || {
+            for _ in 0..num_records {
+                let result: Result<Vec<i16>, EdfError> = read_strided_record(&mut reader, record_size, endian, stride);
+                let failed: bool = result.is_err();
+                // The receiver is gone once the conversion stops early.
+                if sender.send(result).is_err() || failed {
+                    return;
+                }
             }
-            writer.write_record(&row)?;
+        });
+        RecordSource::Prefetched(receiver)
+    }
 
-            timestamp = increment_timestamp(timestamp, sample_interval);
+    fn next_record(&mut self) -> Result<Vec<i16>, EdfError> {
+        match self {
+            RecordSource::Direct {reader, record_size, endian, stride} => read_strided_record(reader, *record_size, *endian, *stride),
+            RecordSource::Prefetched(records) => match records.recv() {
+                Ok(result) => result,
+                Err(_) => Err(EdfError::Io("the record reader thread stopped unexpectedly".to_string()))
+            }
         }
     }
-    Ok(())
 }
 
 
-fn is_edf_file(file_path: &Path) -> bool {
-    file_path.is_file() && file_path.extension().unwrap() == "edf"
+// Reads a record and then skips the `stride - 1` after it: a seek for local
+// files and inputs in memory, a read that discards them for streams. The last
+// record may be followed by fewer than a full stride.
+fn read_strided_record(reader: &mut EdfReader, record_size: usize, endian: Endian, stride: usize) -> Result<Vec<i16>, EdfError> {
+    let values: Vec<i16> = read_record_samples_with_endian(reader, record_size, endian)?;
+    let skipped_bytes: u64 = ((stride - 1) * record_size * 2) as u64;
+    skip_input(reader, skipped_bytes)?;
+    Ok(values)
 }
 
 
-fn list_edf_files(dir_path: &PathBuf) -> Vec<PathBuf> {
-    let mut edf_list: Vec<PathBuf> = Vec::new();
-
-    let dir_contents = fs::read_dir(dir_path)
-        .unwrap()
-        .map(|e| e.unwrap().path());
+// Reads a ZIP entry that another thread decompresses into a bounded channel,
+// so at most `ZIP_STREAM_CHUNKS` chunks are buffered at any time.
+struct ZipEntryReader {
+    chunks: mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Cursor<Vec<u8>>
+}
+impl ZipEntryReader {
+    fn open(archive_path: &Path, entry_name: String) -> Result<Self, EdfError> {
+        // Open the entry here once so that a missing one fails right away.
+        let mut archive: ZipArchive<File> = ZipArchive::new(File::open(archive_path)?)?;
+        archive.by_name(&entry_name)?;
 
-    for file_path in dir_contents {
-        if is_edf_file(&file_path) {
-            edf_list.push(file_path)
-        } else if file_path.is_dir() {
-            edf_list.extend(list_edf_files(&file_path))
+        let (sender, receiver) = mpsc::sync_channel(ZIP_STREAM_CHUNKS);
+        thread::spawn(move || {
+            let mut entry = match archive.by_name(&entry_name) {
+                Ok(entry) => entry,
+                Err(error) => {
+                    let _ = sender.send(Err(io::Error::other(error)));
+                    return;
+                }
+            };
+            loop {
+                let mut chunk: Vec<u8> = vec![0; ZIP_STREAM_CHUNK_SIZE];
+                let result: io::Result<Vec<u8>> = match entry.read(&mut chunk) {
+                    Ok(0) => return,
+                    Ok(read) => {
+                        chunk.truncate(read);
+                        Ok(chunk)
+                    },
+                    Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(error) => Err(error)
+                };
+                let failed: bool = result.is_err();
+                // The receiver is gone once the conversion stops reading.
+                if sender.send(result).is_err() || failed {
+                    return;
+                }
+            }
+        });
+        Ok(ZipEntryReader {chunks: receiver, chunk: Cursor::new(Vec::new())})
+    }
+}
+impl Read for ZipEntryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read: usize = self.chunk.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            match self.chunks.recv() {
+                Ok(chunk) => self.chunk = Cursor::new(chunk?),
+                // The thread hung up: the entry is exhausted.
+                Err(_) => return Ok(0)
+            }
         }
     }
-    edf_list
 }
 
 
-fn get_status_logger() -> Writer<File> {
-    let status_file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .append(true)
-        .open("status.txt")
-        .unwrap();
-    
-    WriterBuilder::new()
-        .delimiter(b':')
-        .quote_style(QuoteStyle::Always)
-        .from_writer(status_file)
+fn read_stdin() -> Result<Vec<u8>, String> {
+    let mut contents: Vec<u8> = Vec::new();
+    match io::stdin().lock().read_to_end(&mut contents) {
+        Ok(_) => Ok(contents),
+        Err(e) => Err(e.to_string())
+    }
 }
 
 
-fn main() {
-    let target_dir: &Path = Path::new("./edf_to_csv_files/");
-    fs::create_dir_all(target_dir).unwrap();
+// Output for an EDF inside a ZIP archive goes to a directory named after the
+// archive that mirrors the entry's path; that directory is created as needed.
+fn output_path(file_path: &Path, target_dir: &Path, extension: &str) -> Result<PathBuf, EdfError> {
+    let path: PathBuf = planned_output_path(file_path, target_dir, extension);
+    if split_zip_path(file_path).is_some() {
+        fs::create_dir_all(path.parent().unwrap())?;
+    }
+    Ok(path)
+}
 
 
-    let mut edf_file_paths: Vec<PathBuf> = Vec::new();
-    for arg in env::args().skip(1) {
-        let file_path: PathBuf = PathBuf::from(&arg);
-        if is_edf_file(&file_path) {
-            edf_file_paths.push(file_path)
-        } else if file_path.is_dir() {
-            edf_file_paths.extend(list_edf_files(&file_path))
+// The main files a conversion of `file_path` writes, for `--dry-run-list`.
+// With `--split-signals` there's one per signal, shown with a `*` for the
+// label. With `--per-record-files` there's one per converted record, which
+// takes reading the header; if it can't be read, a `*` stands for the
+// record index.
+fn planned_outputs(file_path: &Path, target_dir: &Path, options: &Options) -> Vec<PathBuf> {
+    if let Some(merge_path) = &options.merge_to {
+        return vec![merge_path.clone()];
+    }
+    if options.per_record_files {
+        let num_records: Option<usize> = open_edf(file_path, options)
+            .and_then(|mut reader| read_edf_header(&mut reader, options))
+            .map(|header| header.num_records)
+            .ok();
+        let Some(num_records) = num_records else {
+            return vec![planned_output_path(file_path, target_dir, "rec*.csv")];
+        };
+        let width: usize = num_records.saturating_sub(1).to_string().len().max(4);
+        let (first, end): (usize, usize) = match options.records {
+            Some((first, last)) => (first, last.unwrap_or(num_records).min(num_records)),
+            None => (0, num_records)
+        };
+        return (first..end)
+            .step_by(options.record_stride)
+            .map(|record| planned_output_path(file_path, target_dir, &format!("rec{:0width$}.csv", record, width = width)))
+            .collect();
+    }
+    let extension: &str = if options.schema_only {
+        match options.schema_format {
+            SchemaFormat::Json => "schema.json",
+            SchemaFormat::Sql => "schema.sql"
         }
+    } else if options.split_signals {
+        "*.csv"
+    } else if options.transpose {
+        "transposed.csv"
+    } else {
+        match options.format {
+            OutputFormat::Csv if options.gzip => "csv.gz",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Npy => "npy",
+            OutputFormat::Replay => "replay.csv",
+            OutputFormat::Sqlite => return vec![options.db.clone().unwrap()]
+        }
+    };
+    vec![planned_output_path(file_path, target_dir, extension)]
+}
+
+
+// Where `output_path` puts a file, without creating anything.
+fn planned_output_path(file_path: &Path, target_dir: &Path, extension: &str) -> PathBuf {
+    let file_name: &OsStr = if file_path == Path::new(STDIN_PATH) {
+        OsStr::new(STDIN_NAME)
+    } else if is_url(file_path) {
+        url_file_name(file_path)
+    } else {
+        file_path.file_name().unwrap()
+    };
+    let target_file: PathBuf = Path::new(file_name).with_extension(extension);
+    match split_zip_path(file_path) {
+        Some((archive_path, entry_name)) => {
+            let mut entry_dir: PathBuf = target_dir.join(archive_path.file_stem().unwrap());
+            if let Some(parent) = Path::new(&entry_name).parent() {
+                entry_dir.push(parent);
+            }
+            entry_dir.join(target_file)
+        },
+        None => target_dir.join(target_file)
     }
-    
-    let mut status_logger: Writer<File> = get_status_logger();
+}
 
-    for mut file_path in edf_file_paths {
-        match parse_edf(&mut file_path, target_dir) {
-            Ok(()) => status_logger.write_record([&LocalDateTime::now().iso().to_string(), file_path.to_str().unwrap(), "File parsed successfully!"]).unwrap(),
-            Err(e) => status_logger.write_record([&LocalDateTime::now().iso().to_string(), file_path.to_str().unwrap(), &e.to_string()]).unwrap()
-        }
+
+// Where parse_edf sends its rows: a CSV file, or a comparison against a CSV
+// written by an earlier run.
+trait RowSink {
+    fn write_row(&mut self, row: &[String]) -> Result<(), EdfError>;
+    fn flush(&mut self) -> Result<(), EdfError>;
+    // Called before the rows of each data record are written.
+    fn start_record(&mut self, _record: usize) -> Result<(), EdfError> {
+        Ok(())
     }
-}
\ No newline at end of file
+    fn finish(&mut self) -> Result<(), EdfError> {
+        self.flush()
+    }
+}
+impl<W: io::Write> RowSink for Writer<W> {
+    fn write_row(&mut self, row: &[String]) -> Result<(), EdfError> {
+        self.write_record(row)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), EdfError> {
+        Writer::flush(self)?;
+        Ok(())
+    }
+}
+
+
+// Takes the place of the output for `--no-write`.
+struct NullSink;
+impl RowSink for NullSink {
+    fn write_row(&mut self, _row: &[String]) -> Result<(), EdfError> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), EdfError> {
+        Ok(())
+    }
+}
+
+
+// `--gzip`: the CSV compressed on the fly into `<name>.csv.gz`. Finishing
+// writes the gzip trailer, which dropping the encoder would do too but
+// without reporting a failure.
+struct GzipCsvWriter {
+    writer: Option<Writer<GzEncoder<File>>>
+}
+impl GzipCsvWriter {
+    fn create(path: &Path, options: &Options) -> Result<Self, EdfError> {
+        let encoder: GzEncoder<File> = GzEncoder::new(File::create(path)?, Compression::new(options.compress_level));
+        Ok(GzipCsvWriter { writer: Some(WriterBuilder::new().delimiter(options.conversion.delimiter()).quote_style(options.quote_style).from_writer(encoder)) })
+    }
+}
+impl RowSink for GzipCsvWriter {
+    fn write_row(&mut self, row: &[String]) -> Result<(), EdfError> {
+        self.writer.as_mut().unwrap().write_record(row)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), EdfError> {
+        self.writer.as_mut().unwrap().flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), EdfError> {
+        if let Some(writer) = self.writer.take() {
+            let encoder: GzEncoder<File> = writer.into_inner().map_err(|e| EdfError::Io(e.error().to_string()))?;
+            encoder.finish()?;
+        }
+        Ok(())
+    }
+}
+
+
+// `--durable`: the CSV goes to `<name>.csv.tmp` next to its final path.
+// Finishing flushes it, syncs it to disk, renames it over the final path and
+// syncs the directory so the rename survives a crash too. A conversion that
+// fails or is abandoned leaves no temporary file and no final file behind.
+struct DurableCsvWriter {
+    writer: Option<Writer<File>>,
+    temp_path: PathBuf,
+    path: PathBuf
+}
+impl DurableCsvWriter {
+    fn create(path: &Path, options: &Options) -> Result<Self, EdfError> {
+        let mut temp_path: OsString = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let temp_path: PathBuf = PathBuf::from(temp_path);
+        let writer: Writer<File> = csv_writer(&temp_path, options)?;
+        Ok(DurableCsvWriter { writer: Some(writer), temp_path, path: path.to_path_buf() })
+    }
+}
+impl RowSink for DurableCsvWriter {
+    fn write_row(&mut self, row: &[String]) -> Result<(), EdfError> {
+        self.writer.as_mut().unwrap().write_record(row)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), EdfError> {
+        self.writer.as_mut().unwrap().flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), EdfError> {
+        if let Some(writer) = self.writer.take() {
+            let file: File = writer.into_inner().map_err(|e| EdfError::Io(e.error().to_string()))?;
+            file.sync_all()?;
+            drop(file);
+            fs::rename(&self.temp_path, &self.path)?;
+            if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                // Directories can't be opened for syncing on every platform;
+                // the data itself is already on disk then.
+                if let Ok(dir) = File::open(dir) {
+                    let _ = dir.sync_all();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+impl Drop for DurableCsvWriter {
+    fn drop(&mut self) {
+        if self.writer.take().is_some() {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+
+// `--per-record-files`: each data record goes to `<name>.rec<n>.csv`, with
+// the record index zero-padded to the width of the highest one (at least 4
+// digits). The header rows written before the first record are repeated at
+// the top of every file.
+struct PerRecordCsvWriter<'a> {
+    file_path: &'a Path,
+    target_dir: &'a Path,
+    width: usize,
+    header: Vec<Vec<String>>,
+    writer: Option<Writer<File>>,
+    options: &'a Options
+}
+impl<'a> PerRecordCsvWriter<'a> {
+    fn new(file_path: &'a Path, target_dir: &'a Path, num_records: usize, options: &'a Options) -> Self {
+        PerRecordCsvWriter {
+            file_path,
+            target_dir,
+            width: num_records.saturating_sub(1).to_string().len().max(4),
+            header: Vec::new(),
+            writer: None,
+            options
+        }
+    }
+}
+impl RowSink for PerRecordCsvWriter<'_> {
+    fn write_row(&mut self, row: &[String]) -> Result<(), EdfError> {
+        match self.writer.as_mut() {
+            Some(writer) => writer.write_record(row)?,
+            None => self.header.push(row.to_vec())
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), EdfError> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn start_record(&mut self, record: usize) -> Result<(), EdfError> {
+        self.finish()?;
+        let extension: String = format!("rec{:0width$}.csv", record, width = self.width);
+        let mut writer: Writer<File> = csv_writer(&output_path(self.file_path, self.target_dir, &extension)?, self.options)?;
+        for row in &self.header {
+            writer.write_record(row)?;
+        }
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), EdfError> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+
+// Streams rows into a `.npy` matrix of little-endian f64 with one column per
+// signal and NaN for blank samples. The header fixes the shape, so the number
+// of rows has to be known up front. Timestamps go to the JSON sidecar instead.
+struct NpyWriter {
+    writer: BufWriter<File>,
+    rows_expected: usize,
+    rows_written: usize
+}
+impl NpyWriter {
+    fn create(npy_path: &Path, num_rows: usize, num_columns: usize) -> Result<Self, EdfError> {
+        let mut writer: BufWriter<File> = BufWriter::new(File::create(npy_path)?);
+        let mut header: String = format!("{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, {}), }}", num_rows, num_columns);
+        // Magic, version and length take 10 bytes; the header is padded so
+        // that the data starts on a 64 byte boundary and ends with a newline.
+        let padding: usize = (64 - (10 + header.len() + 1) % 64) % 64;
+        header.push_str(&" ".repeat(padding));
+        header.push('\n');
+        writer.write_all(b"\x93NUMPY\x01\x00")?;
+        writer.write_all(&(header.len() as u16).to_le_bytes())?;
+        writer.write_all(header.as_bytes())?;
+        Ok(NpyWriter { writer, rows_expected: num_rows, rows_written: 0 })
+    }
+}
+impl RowSink for NpyWriter {
+    fn write_row(&mut self, row: &[String]) -> Result<(), EdfError> {
+        for value in row.iter().skip(1) {
+            let value: f64 = if value.is_empty() { f64::NAN } else { value.parse()? };
+            self.writer.write_all(&value.to_le_bytes())?;
+        }
+        self.rows_written += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), EdfError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), EdfError> {
+        self.flush()?;
+        if self.rows_written != self.rows_expected {
+            let message: String = format!("wrote {} rows to an npy file declared with {}", self.rows_written, self.rows_expected);
+            return Err(EdfError::Io(message));
+        }
+        Ok(())
+    }
+}
+
+
+// `<name>.npy.json`: what the columns of the npy matrix are and when its
+// rows were sampled.
+#[derive(Serialize)]
+struct NpySidecar<'a> {
+    labels: &'a [String],
+    dimensions: &'a [String],
+    start: String,
+    // Exact, so it needn't be a whole number; `null` without samples.
+    sample_interval_ms: Option<f64>,
+    num_rows: usize
+}
+
+
+// Inserts the rows into a table of an SQLite database, named after the file,
+// with a text timestamp and a REAL column per signal. The table is replaced
+// in one transaction, which keeps inserting fast and leaves the old table
+// in place if the conversion fails.
+struct SqliteWriter {
+    connection: Connection,
+    insert: String
+}
+impl SqliteWriter {
+    fn create(db_path: &Path, file_stem: &str, labels: &[String], options: &Options) -> Result<Self, EdfError> {
+        let connection: Connection = Connection::open(db_path).map_err(sqlite_error)?;
+        let timestamp_column: &str = options.conversion.timestamp_column();
+        let table: String = sql_safe_labels(&[file_stem.to_string()], timestamp_column).remove(0);
+        let columns: Vec<String> = sql_safe_labels(labels, timestamp_column).iter().map(|label| format!(", \"{}\" REAL", label)).collect();
+        connection.execute_batch(&format!(
+            "BEGIN; DROP TABLE IF EXISTS \"{}\"; CREATE TABLE \"{}\" (\"{}\" TEXT{});",
+            table, table, timestamp_column.replace('"', "\"\""), columns.concat()
+        )).map_err(sqlite_error)?;
+        let insert: String = format!("INSERT INTO \"{}\" VALUES (?{})", table, ", ?".repeat(labels.len()));
+        Ok(SqliteWriter { connection, insert })
+    }
+}
+impl RowSink for SqliteWriter {
+    fn write_row(&mut self, row: &[String]) -> Result<(), EdfError> {
+        let mut values: Vec<Value> = Vec::with_capacity(row.len());
+        values.push(if row[0].is_empty() { Value::Null } else { Value::Text(row[0].clone()) });
+        for value in &row[1..] {
+            values.push(if value.is_empty() { Value::Null } else { Value::Real(value.parse()?) });
+        }
+        self.connection.prepare_cached(&self.insert).and_then(|mut insert| insert.execute(params_from_iter(values))).map_err(sqlite_error)?;
+        Ok(())
+    }
+
+    // Rows only become visible when the transaction is committed.
+    fn flush(&mut self) -> Result<(), EdfError> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), EdfError> {
+        self.connection.execute_batch("COMMIT;").map_err(sqlite_error)?;
+        Ok(())
+    }
+}
+
+
+// The library's error type doesn't know about SQLite, which only the SQLite
+// output uses, so its errors are turned into EdfErrors here.
+fn sqlite_error(error: rusqlite::Error) -> EdfError {
+    EdfError::Sqlite(error.to_string())
+}
+
+
+// Streams an existing CSV alongside the conversion and stops at the first row
+// that differs, so nothing is buffered beyond the current row.
+struct CsvVerifier {
+    records: StringRecordsIntoIter<File>,
+    rows_checked: usize
+}
+impl CsvVerifier {
+    fn open(csv_path: &Path, options: &Options) -> Result<Self, EdfError> {
+        let reader: Reader<File> = ReaderBuilder::new()
+            .has_headers(false)
+            .delimiter(options.conversion.delimiter())
+            .flexible(true)
+            .from_path(csv_path)?;
+        Ok(CsvVerifier { records: reader.into_records(), rows_checked: 0 })
+    }
+}
+impl RowSink for CsvVerifier {
+    fn write_row(&mut self, row: &[String]) -> Result<(), EdfError> {
+        self.rows_checked += 1;
+        let record: StringRecord = match self.records.next() {
+            Some(record) => record?,
+            None => {
+                let message: String = format!("the CSV ends after {} rows but the EDF file has more", self.rows_checked - 1);
+                return Err(EdfError::VerificationFailed(message));
+            }
+        };
+        if record.iter().ne(row.iter().map(|value| value.as_str())) {
+            let found: Vec<&str> = record.iter().collect();
+            let message: String = format!("row {} is \"{}\" but the EDF file gives \"{}\"", self.rows_checked, found.join(","), row.join(","));
+            return Err(EdfError::VerificationFailed(message));
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), EdfError> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), EdfError> {
+        if self.records.next().is_some() {
+            let message: String = format!("the CSV has more than the {} rows converted from the EDF file", self.rows_checked);
+            return Err(EdfError::VerificationFailed(message));
+        }
+        Ok(())
+    }
+}
+
+
+// A single CSV that several files are appended to, with a leading
+// `source_file` column. The first file merged fixes the columns.
+struct MergeTarget {
+    writer: Writer<File>,
+    columns: Option<(Vec<String>, Vec<String>)>
+}
+
+
+// Converts one file as a stream: samples are read, scaled and written one data
+// record at a time, so memory use is bounded by the record size and not by the
+// length of the recording. `--low-memory` extends that to ZIP entries and stdin.
+// Returns the number of samples converted.
+// `cancel` is checked before every record; once it's set, the conversion
+// stops with a timeout error before finishing its output.
+fn parse_edf(file_path: &Path, target_dir: &Path, options: &Options, merge: Option<&mut MergeTarget>, cancel: Option<&AtomicBool>) -> Result<usize, EdfError> {
+    let started: Instant = Instant::now();
+    let mut reader: EdfReader = open_edf(file_path, options)?;
+
+    let header: EdfHeader = read_prepared_header(file_path, &mut reader, options)?;
+    if options.metadata && !options.verify && !options.no_write {
+        write_metadata(file_path, target_dir, &header, options)?;
+    }
+    let inline_values: Vec<String> = options.inline_metadata
+        .iter()
+        .map(|&(_, field)| field.value(file_path, &header).unwrap_or_else(|| missing_cell(options)))
+        .collect();
+
+    let start: NaiveDateTime = header.start;
+    let mut timestamp: NaiveDateTime = header.start;
+    let num_records: usize = header.num_records;
+    let data_offset: u64 = header.data_offset;
+    let record_duration: f64 = header.record_duration;
+    let signals: Vec<Signal> = header.signals;
+    let record_size: usize = signals.iter().map(|s| s.num_samples).sum();
+
+    // Annotation signals hold TAL bytes, not samples, so they get no column
+    // and are only read for `--events-csv`.
+    let data_signals: Vec<&Signal> = signals.iter().filter(|s| !s.is_annotation()).collect();
+    let order: Vec<usize> = column_order(file_path, &data_signals, record_duration, options);
+    let columns: Vec<&Signal> = order.iter().map(|&index| data_signals[index]).collect();
+    let num_signals: usize = columns.len();
+
+    // The highest-rate signal sets the rows unless `--timeline-signal` names
+    // another; the others are aligned to it. Signals without samples are
+    // placeholders that get an always-blank column.
+    let timeline: Timeline = Timeline::new(&columns, record_duration, &options.conversion)?;
+    let num_samples: usize = timeline.rows_per_record();
+    let sample_interval: Duration = timeline.row_interval();
+
+    let records: Range<usize> = skip_to_records(file_path, &mut reader, num_records, data_offset, record_size, options)?;
+    timestamp += records_offset(file_path, &timeline, records.start)?;
+
+    let mut labels: Vec<String> = columns.iter().map(|s| s.label.clone()).collect();
+    if options.sql_safe_headers {
+        labels = sql_safe_labels(&labels, options.conversion.timestamp_column());
+    }
+    let dimensions: Vec<String> = columns.iter().map(|s| s.dimension.clone()).collect();
+    let rates: Vec<String> = columns.iter().map(|s| s.sample_rate(record_duration).to_string()).collect();
+
+    let mut events: Option<Writer<File>> = None;
+    if options.events_csv && !options.verify && !options.no_write {
+        events = Some(create_events_writer(file_path, target_dir, options)?);
+    }
+
+    let source: Option<String> = merge.as_ref().map(|_| file_path.to_string_lossy().to_string());
+    let mut own_writer: Writer<File>;
+    let mut verifier: CsvVerifier;
+    let mut npy_writer: NpyWriter;
+    let mut gzip_writer: GzipCsvWriter;
+    let mut durable_writer: DurableCsvWriter;
+    let mut per_record_writer: PerRecordCsvWriter;
+    let mut sqlite_writer: SqliteWriter;
+    let mut null_sink: NullSink = NullSink;
+    let (writer, write_header): (&mut dyn RowSink, bool) = match merge {
+        Some(target) => {
+            let first_file: bool = target.columns.is_none();
+            match &target.columns {
+                Some((merged_labels, merged_dimensions)) if *merged_labels != labels || *merged_dimensions != dimensions => {
+                    let message: String = format!("{}: Signals don't match the files already merged into the output!", &file_path.to_string_lossy());
+                    return Err(EdfError::SchemaMismatch(message));
+                },
+                Some(_) => {},
+                None => target.columns = Some((labels.clone(), dimensions.clone()))
+            }
+            (&mut target.writer, first_file)
+        },
+        None if options.no_write => (&mut null_sink, false),
+        None if options.verify => {
+            verifier = CsvVerifier::open(&output_path(file_path, target_dir, "csv")?, options)?;
+            (&mut verifier, true)
+        },
+        None if options.format == OutputFormat::Npy => {
+            let num_rows: usize = (records.len().div_ceil(options.record_stride) * num_samples).min(options.limit_rows.unwrap_or(usize::MAX));
+            let sidecar: NpySidecar = NpySidecar {
+                labels: &labels,
+                dimensions: &dimensions,
+                start: timestamp.format(options.conversion.datetime_format()).to_string(),
+                sample_interval_ms: timeline.row_interval_ms(),
+                num_rows
+            };
+            let contents: String = serde_json::to_string_pretty(&sidecar).unwrap();
+            fs::write(output_path(file_path, target_dir, "npy.json")?, contents + "\n")?;
+            npy_writer = NpyWriter::create(&output_path(file_path, target_dir, "npy")?, num_rows, num_signals)?;
+            (&mut npy_writer, false)
+        },
+        None if options.format == OutputFormat::Sqlite => {
+            let db_path: &Path = options.db.as_deref().unwrap();
+            let file_stem: String = if file_path == Path::new(STDIN_PATH) { STDIN_NAME.to_string() } else { file_path.file_stem().unwrap().to_string_lossy().to_string() };
+            sqlite_writer = SqliteWriter::create(db_path, &file_stem, &labels, options)?;
+            (&mut sqlite_writer, false)
+        },
+        None if options.gzip => {
+            gzip_writer = GzipCsvWriter::create(&output_path(file_path, target_dir, "csv.gz")?, options)?;
+            (&mut gzip_writer, true)
+        },
+        None if options.per_record_files => {
+            per_record_writer = PerRecordCsvWriter::new(file_path, target_dir, num_records, options);
+            (&mut per_record_writer, true)
+        },
+        None if options.durable => {
+            durable_writer = DurableCsvWriter::create(&output_path(file_path, target_dir, "csv")?, options)?;
+            (&mut durable_writer, true)
+        },
+        None => {
+            own_writer = csv_writer(&output_path(file_path, target_dir, "csv")?, options)?;
+            (&mut own_writer, true)
+        }
+    };
+    let mut row: Vec<String> = Vec::with_capacity(2 + num_signals);
+
+    if options.units_sidecar && !options.verify && !options.no_write {
+        write_units(file_path, target_dir, &labels, &dimensions, options)?;
+    }
+    if write_header && !options.no_header {
+        if source.is_some() {
+            row.push("source_file".to_string());
+        }
+        row.extend(options.inline_metadata.iter().map(|(column, _)| column.clone()));
+        row.push(options.conversion.timestamp_column().to_string());
+        if options.sample_offset_column {
+            row.push("sample_offset".to_string());
+        }
+        row.extend(labels);
+        writer.write_row(&row)?;
+
+        if !options.units_sidecar {
+            row.clear();
+            if source.is_some() {
+                row.push("".to_string());
+            }
+            row.extend(inline_values.iter().map(|_| String::new()));
+            row.push(timestamp_unit(options).to_string());
+            if options.sample_offset_column {
+                row.push("".to_string());
+            }
+            row.extend(dimensions);
+            writer.write_row(&row)?;
+        }
+
+        if options.rate_header {
+            row.clear();
+            if source.is_some() {
+                row.push("".to_string());
+            }
+            row.extend(inline_values.iter().map(|_| String::new()));
+            row.push("".to_string());
+            if options.sample_offset_column {
+                row.push("".to_string());
+            }
+            row.extend(rates);
+            writer.write_row(&row)?;
+        }
+    }
+
+    // Formats a record's rows; each row starts at the record's first timestamp.
+    let format_record = |timestamps: &[NaiveDateTime], scaled: &[Vec<Option<f64>>]| -> Vec<Vec<String>> {
+        let mut rows: Vec<Vec<String>> = Vec::with_capacity(num_samples);
+        for (i, &timestamp) in timestamps.iter().enumerate() {
+            let mut row: Vec<String> = Vec::with_capacity(2 + num_signals);
+            if let Some(source) = &source {
+                row.push(source.clone());
+            }
+            row.extend(inline_values.iter().cloned());
+            if options.sparse_timestamps && i > 0 {
+                row.push(String::new());
+            } else {
+                row.push(format_timestamp(timestamp, start, options));
+            }
+            if options.sample_offset_column {
+                row.push(i.to_string());
+            }
+
+            for &index in &order {
+                let cleaned_val: String = match timeline.sample(i, &scaled[index]) {
+                    Some(scaled) => format_value(scaled, options),
+                    None => missing_cell(options)
+                };
+                row.push(cleaned_val);
+            }
+            rows.push(row);
+        }
+        rows
+    };
+
+    // Scaling stays sequential; with several threads per file, formatting,
+    // which costs far more, runs on batches of records split across threads.
+    // The batches are written in record order, so the CSV doesn't change.
+    let threads: usize = options.threads_per_file.max(1);
+    let batch_size: usize = if threads > 1 { (PARALLEL_BATCH_ROWS / num_samples.max(1)).max(threads) } else { 1 };
+    let mut batch: Vec<ScaledRecord> = Vec::with_capacity(batch_size);
+    let mut last_row_timestamp: Option<NaiveDateTime> = None;
+    let stride: usize = options.record_stride;
+    let first_record: usize = records.start;
+    let last_record: usize = first_record + records.len().saturating_sub(1) / stride * stride;
+    let records_end: usize = records.end;
+    let mut shift: Duration = Duration::zero();
+
+    let mut samples_scaled: usize = 0;
+    let mut stats: Option<Vec<SignalStats>> = None;
+    if options.stats && !options.verify {
+        stats = Some(data_signals.iter().map(|signal| SignalStats::new(signal, record_duration, options)).collect());
+    }
+    let mut coverage: Option<Vec<SignalCoverage>> = None;
+    if options.coverage && !options.verify {
+        coverage = Some(data_signals.iter().map(|signal| SignalCoverage::new(signal, record_duration)).collect());
+    }
+    let converted_sec: f64 = records.len() as f64 * record_duration;
+    let mut rows_left: usize = options.limit_rows.unwrap_or(usize::MAX);
+    let mut truncated: bool = false;
+    let mut clock: Option<RecordClock> = options.verbose.then(RecordClock::new);
+    let mut source: RecordSource = RecordSource::new(reader, record_size, records.len().div_ceil(stride), options);
+    let cancelled = || {
+        let message: String = format!("{}: conversion cancelled after the timeout", file_path.to_string_lossy());
+        EdfError::Timeout(message)
+    };
+    let mut gap_filler: GapFiller = GapFiller::new(data_signals.len(), options);
+    // The extra last step releases the records `GapFiller` still holds.
+    for record in records.step_by(stride).map(Some).chain([None]) {
+        if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            return Err(cancelled());
+        }
+        if rows_left == 0 && record.is_some() {
+            truncated = true;
+            break;
+        }
+        let ready: Vec<PendingRecord> = match record {
+            Some(record) => {
+                let values: Vec<i16> = read_next_record(&mut source, file_path, record, records_end, data_offset, record_size, options)?;
+                if let Some(clock) = clock.as_mut() {
+                    clock.add(time_keeping_onset(&signals, &values, options.conversion.endian()), record_duration * stride as f64);
+                }
+                if record == first_record {
+                    shift = fractional_start(&signals, &values, record, record_duration, options.conversion.endian());
+                    timestamp += shift;
+                }
+                let mut scaled: Vec<Vec<Option<f64>>> = Vec::with_capacity(num_signals);
+                let mut offset: usize = 0;
+                // EDF+ allows several annotation signals; their TALs are gathered per
+                // record and written in onset order.
+                let mut tals: Vec<Tal> = Vec::new();
+                for signal in &signals {
+                    if signal.is_annotation() {
+                        if events.is_some() {
+                            tals.extend(parse_tals(&annotation_bytes(&values[offset..offset + signal.num_samples], options.conversion.endian())));
+                        }
+                        offset += signal.num_samples;
+                        continue;
+                    }
+                    if signal.num_samples == 0 {
+                        scaled.push(vec![None; num_samples]);
+                        continue;
+                    }
+                    let raw_values: &[i16] = &values[offset..offset + signal.num_samples];
+                    if record == first_record {
+                        check_byte_order(file_path, signal, raw_values, options);
+                    }
+                    offset += signal.num_samples;
+                    scaled.push(scale_signal(signal, raw_values, options));
+                }
+
+                if let Some(events_writer) = events.as_mut() {
+                    tals.sort_by(|a, b| a.onset.total_cmp(&b.onset));
+                    write_events(events_writer, start, &tals, options)?;
+                }
+                gap_filler.push(record, values, scaled)
+            },
+            None => gap_filler.finish()
+        };
+
+        for (record, values, mut scaled) in ready {
+            for (index, (signal, raw_values)) in data_samples(&signals, &values).enumerate() {
+                if signal.num_samples == 0 {
+                    continue;
+                }
+                let signal_values: &mut Vec<Option<f64>> = &mut scaled[index];
+                if let Some(stats) = stats.as_mut() {
+                    stats[index].add(signal_values);
+                }
+                blank_non_finite(signal_values);
+                if let Some(coverage) = coverage.as_mut() {
+                    coverage[index].add(signal, record, raw_values, signal_values);
+                }
+            }
+            samples_scaled += data_signals.iter().map(|s| s.num_samples).sum::<usize>();
+            if options.no_write {
+                continue;
+            }
+
+            let mut row_timestamps: Vec<NaiveDateTime> = Vec::with_capacity(num_samples);
+            for i in 0..num_samples {
+                row_timestamps.push(dedup_timestamp(file_path, timestamp + sample_interval * i as i32, &mut last_row_timestamp, options)?);
+            }
+            batch.push((record, row_timestamps, scaled));
+            timestamp += sample_interval * (num_samples * stride) as i32;
+            if batch.len() < batch_size && record != last_record {
+                continue;
+            }
+
+            let formatted: Vec<Vec<Vec<String>>> = if threads > 1 {
+                let format_record = &format_record;
+                thread::scope(|scope| {
+                    let handles: Vec<thread::ScopedJoinHandle<Vec<Vec<Vec<String>>>>> = batch
+                        .chunks(batch.len().div_ceil(threads))
+                        .map(|chunk| scope.spawn(move || chunk.iter().map(|(_, timestamps, scaled)| format_record(timestamps, scaled)).collect()))
+                        .collect();
+                    handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+                })
+            } else {
+                batch.iter().map(|(_, timestamps, scaled)| format_record(timestamps, scaled)).collect()
+            };
+            for (&(record, _, _), rows) in batch.iter().zip(formatted) {
+                truncated |= rows.len() > rows_left;
+                if rows_left > 0 {
+                    writer.start_record(record)?;
+                }
+                for row in rows.into_iter().take(rows_left) {
+                    writer.write_row(&row)?;
+                    rows_left -= 1;
+                }
+                if options.flush_every > 0 && (record + 1) % options.flush_every == 0 {
+                    writer.flush()?;
+                }
+            }
+            batch.clear();
+        }
+    }
+    if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+        return Err(cancelled());
+    }
+    // Dropping the writer would flush too, but would swallow any error.
+    writer.finish()?;
+    if truncated {
+        warn(file_path, &format!("output truncated after {} rows (--limit-rows)", options.limit_rows.unwrap()));
+    }
+    // Skipped records leave the last timestamp short of the recording's end.
+    if options.validate_timestamps && !options.no_write && !truncated && stride == 1 {
+        check_end_time(file_path, None, timestamp, start + shift, records_end, record_duration, options)?;
+    }
+    if let Some(mut events_writer) = events {
+        events_writer.flush()?;
+    }
+    if let Some(stats) = stats {
+        write_stats(file_path, target_dir, order.iter().map(|&index| &stats[index]).collect())?;
+    }
+    if let Some(coverage) = coverage {
+        write_coverage(file_path, target_dir, start, converted_sec, order.iter().map(|&index| &coverage[index]).collect())?;
+    }
+    if options.no_write {
+        let seconds: f64 = started.elapsed().as_secs_f64();
+        println!(
+            "{}: {} samples read and scaled in {:.3} s ({:.0} samples/s)",
+            file_path.to_string_lossy(), samples_scaled, seconds, samples_scaled as f64 / seconds
+        );
+    }
+    if let Some(clock) = clock {
+        clock.report(file_path, record_duration, &columns);
+    }
+    Ok(samples_scaled)
+}
+
+
+// `--validate-timestamps`: the timestamp after the last row written should be
+// the end of the last converted record, as the header's record duration puts
+// it. Whole milliseconds of difference are drift, e.g. from a sample interval
+// that the nanosecond rounding has pushed off over a very long recording.
+fn check_end_time(
+    file_path: &Path, label: Option<&str>, next_timestamp: NaiveDateTime, start: NaiveDateTime,
+    records_end: usize, record_duration: f64, options: &Options
+) -> Result<(), EdfError> {
+    let expected: NaiveDateTime = start + Duration::nanoseconds((records_end as f64 * record_duration * 1e9).round() as i64);
+    let drift_ms: i64 = (next_timestamp - expected).num_milliseconds();
+    if drift_ms == 0 {
+        return Ok(());
+    }
+    let mut message: String = format!(
+        "timestamps end at {} instead of {} ({:+} ms)",
+        next_timestamp.format(DEFAULT_DATETIME_FORMAT), expected.format(DEFAULT_DATETIME_FORMAT), drift_ms
+    );
+    if let Some(label) = label {
+        message = format!("signal '{}': {}", label, message);
+    }
+    if options.strict {
+        return Err(EdfError::TimestampDrift(format!("{}: {}", &file_path.to_string_lossy(), message)));
+    }
+    warn(file_path, &message);
+    Ok(())
+}
+
+
+// `--verbose`: how far apart the records really are by their time-keeping
+// TALs, to hold against the declared record duration and catch a drifting
+// recorder clock or a misdeclared rate. Steps more than half a record off
+// are EDF+D gaps rather than drift and are left out.
+struct RecordClock {
+    previous: Option<f64>,
+    span: f64,
+    steps: usize
+}
+impl RecordClock {
+    fn new() -> Self {
+        RecordClock { previous: None, span: 0.0, steps: 0 }
+    }
+
+    fn add(&mut self, onset: Option<f64>, record_duration: f64) {
+        if let (Some(previous), Some(onset)) = (self.previous, onset) {
+            let step: f64 = onset - previous;
+            if (step - record_duration).abs() < record_duration / 2.0 {
+                self.span += step;
+                self.steps += 1;
+            }
+        }
+        self.previous = onset;
+    }
+
+    fn report(&self, file_path: &Path, record_duration: f64, signals: &[&Signal]) {
+        if self.steps == 0 {
+            return;
+        }
+        let measured: f64 = self.span / self.steps as f64;
+        println!(
+            "{}: records are {:.6} s apart by their time-keeping annotations, {} s declared ({:+.0} ppm)",
+            file_path.to_string_lossy(), measured, record_duration, (measured / record_duration - 1.0) * 1e6
+        );
+        for signal in signals.iter().filter(|s| s.num_samples > 0) {
+            println!(
+                "  {}: {:.3} Hz effective, {} Hz declared",
+                signal.label, signal.num_samples as f64 / measured, signal.sample_rate(record_duration)
+            );
+        }
+    }
+}
+
+
+// `<name>.units.csv`: a label,dimension row for the time column and for each
+// signal, for loaders that can't take a second header row in the data.
+fn write_units(file_path: &Path, target_dir: &Path, labels: &[String], dimensions: &[String], options: &Options) -> Result<(), EdfError> {
+    let mut writer: Writer<File> = csv_writer(&output_path(file_path, target_dir, "units.csv")?, options)?;
+    writer.write_record(["label", "dimension"])?;
+    writer.write_record([options.conversion.timestamp_column(), timestamp_unit(options)])?;
+    if options.sample_offset_column {
+        writer.write_record(["sample_offset", ""])?;
+    }
+    for (label, dimension) in labels.iter().zip(dimensions) {
+        writer.write_record([label, dimension])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+
+// `<name>.events.csv` with its header row.
+fn create_events_writer(file_path: &Path, target_dir: &Path, options: &Options) -> Result<Writer<File>, EdfError> {
+    let mut writer: Writer<File> = csv_writer(&output_path(file_path, target_dir, "events.csv")?, options)?;
+    let mut header: Vec<&str> = vec!["onset_sec", "duration_sec", "onset_timestamp", "text"];
+    if options.standard_texts {
+        header.push("standard");
+    }
+    writer.write_record(header)?;
+    Ok(writer)
+}
+
+
+// One row per annotation text; time-keeping TALs have no text and are skipped.
+fn write_events(writer: &mut Writer<File>, start: NaiveDateTime, tals: &[Tal], options: &Options) -> Result<(), EdfError> {
+    for tal in tals {
+        let onset: NaiveDateTime = start + Duration::nanoseconds((tal.onset * 1e9).round() as i64);
+        let onset_timestamp: String = onset.format(options.conversion.datetime_format()).to_string();
+        let duration: String = tal.duration.map(|d| d.to_string()).unwrap_or_default();
+        for text in &tal.texts {
+            let mut row: Vec<String> = vec![tal.onset.to_string(), duration.clone(), onset_timestamp.clone(), text.clone()];
+            if options.standard_texts {
+                row.push(is_standard_text(text).to_string());
+            }
+            writer.write_record(row)?;
+        }
+    }
+    Ok(())
+}
+
+
+// `--split-signals`: one CSV per signal, `<name>.<label>.csv`, each at the
+// signal's own sample rate, so signals needn't share a rate. Returns the
+// number of samples written.
+fn split_edf(file_path: &Path, target_dir: &Path, options: &Options) -> Result<usize, EdfError> {
+    let mut reader: EdfReader = open_edf(file_path, options)?;
+    let header: EdfHeader = read_prepared_header(file_path, &mut reader, options)?;
+    if options.metadata {
+        write_metadata(file_path, target_dir, &header, options)?;
+    }
+
+    let start: NaiveDateTime = header.start;
+    let record_duration: f64 = header.record_duration;
+    let signals: Vec<Signal> = header.signals;
+    let record_size: usize = signals.iter().map(|s| s.num_samples).sum();
+    let data_signals: Vec<&Signal> = signals.iter().filter(|s| !s.is_annotation()).collect();
+    let order: Vec<usize> = column_order(file_path, &data_signals, record_duration, options);
+    let records: Range<usize> = skip_to_records(file_path, &mut reader, header.num_records, header.data_offset, record_size, options)?;
+
+    let mut events: Option<Writer<File>> = None;
+    if options.events_csv {
+        events = Some(create_events_writer(file_path, target_dir, options)?);
+    }
+
+    // Each split signal's writer, sample interval and next timestamp, by its
+    // index among the data signals.
+    let mut outputs: Vec<Option<(Writer<File>, Duration, NaiveDateTime)>> = (0..data_signals.len()).map(|_| None).collect();
+    let labels: Vec<&str> = order.iter().map(|&index| data_signals[index].label.as_str()).collect();
+    let mut header_labels: Vec<String> = Vec::with_capacity(order.len());
+    for (&index, file_label) in order.iter().zip(sanitize_file_labels(&labels)) {
+        let signal: &Signal = data_signals[index];
+        let mut writer: Writer<File> = csv_writer(&output_path(file_path, target_dir, &format!("{}.csv", file_label))?, options)?;
+        let mut label: String = signal.label.clone();
+        if options.sql_safe_headers {
+            label = sql_safe_labels(&[label], options.conversion.timestamp_column()).remove(0);
+        }
+        if !options.no_header {
+            writer.write_record([options.conversion.timestamp_column(), label.as_str()])?;
+            if !options.units_sidecar {
+                writer.write_record([timestamp_unit(options), signal.dimension.as_str()])?;
+            }
+            if options.rate_header {
+                writer.write_record(["".to_string(), signal.sample_rate(record_duration).to_string()])?;
+            }
+        }
+        let timeline: Timeline = Timeline::of_signal(signal, record_duration);
+        let timestamp: NaiveDateTime = start + records_offset(file_path, &timeline, records.start)?;
+        outputs[index] = Some((writer, timeline.row_interval(), timestamp));
+        header_labels.push(label);
+    }
+    if options.units_sidecar {
+        let dimensions: Vec<String> = order.iter().map(|&index| data_signals[index].dimension.clone()).collect();
+        write_units(file_path, target_dir, &header_labels, &dimensions, options)?;
+    }
+
+    let mut stats: Option<Vec<SignalStats>> = None;
+    if options.stats {
+        stats = Some(data_signals.iter().map(|signal| SignalStats::new(signal, record_duration, options)).collect());
+    }
+    let mut coverage: Option<Vec<SignalCoverage>> = None;
+    if options.coverage {
+        coverage = Some(data_signals.iter().map(|signal| SignalCoverage::new(signal, record_duration)).collect());
+    }
+    let converted_sec: f64 = records.len() as f64 * record_duration;
+    let first_record: usize = records.start;
+    let records_end: usize = records.end;
+    let mut samples_written: usize = 0;
+    let mut shift: Duration = Duration::zero();
+    let mut clock: Option<RecordClock> = options.verbose.then(RecordClock::new);
+    let mut source: RecordSource = RecordSource::new(reader, record_size, records.len(), options);
+    let mut gap_filler: GapFiller = GapFiller::new(data_signals.len(), options);
+    // The extra last step releases the records `GapFiller` still holds.
+    for record in records.map(Some).chain([None]) {
+        let ready: Vec<PendingRecord> = match record {
+            Some(record) => {
+                let values: Vec<i16> = read_next_record(&mut source, file_path, record, records_end, header.data_offset, record_size, options)?;
+                if let Some(clock) = clock.as_mut() {
+                    clock.add(time_keeping_onset(&signals, &values, options.conversion.endian()), record_duration);
+                }
+                if record == first_record {
+                    shift = fractional_start(&signals, &values, record, record_duration, options.conversion.endian());
+                    for (_, _, timestamp) in outputs.iter_mut().flatten() {
+                        *timestamp += shift;
+                    }
+                }
+                let mut offset: usize = 0;
+                let mut tals: Vec<Tal> = Vec::new();
+                let mut scaled: Vec<Vec<Option<f64>>> = Vec::with_capacity(data_signals.len());
+                for signal in &signals {
+                    let raw_values: &[i16] = &values[offset..offset + signal.num_samples];
+                    offset += signal.num_samples;
+                    if signal.is_annotation() {
+                        if events.is_some() {
+                            tals.extend(parse_tals(&annotation_bytes(raw_values, options.conversion.endian())));
+                        }
+                        continue;
+                    }
+                    // Signals that aren't written aren't scaled either.
+                    if outputs[scaled.len()].is_none() {
+                        scaled.push(Vec::new());
+                        continue;
+                    }
+                    if record == first_record {
+                        check_byte_order(file_path, signal, raw_values, options);
+                    }
+                    scaled.push(scale_signal(signal, raw_values, options));
+                }
+
+                if let Some(events_writer) = events.as_mut() {
+                    tals.sort_by(|a, b| a.onset.total_cmp(&b.onset));
+                    write_events(events_writer, start, &tals, options)?;
+                }
+                gap_filler.push(record, values, scaled)
+            },
+            None => gap_filler.finish()
+        };
+
+        for (record, values, scaled) in ready {
+            for ((index, (signal, raw_values)), mut signal_values) in data_samples(&signals, &values).enumerate().zip(scaled) {
+                let Some((writer, sample_interval, timestamp)) = outputs[index].as_mut() else {
+                    continue;
+                };
+                if let Some(stats) = stats.as_mut() {
+                    stats[index].add(&signal_values);
+                }
+                blank_non_finite(&mut signal_values);
+                if let Some(coverage) = coverage.as_mut() {
+                    coverage[index].add(signal, record, raw_values, &signal_values);
+                }
+                samples_written += signal_values.len();
+                for value in signal_values {
+                    let cleaned_val: String = match value {
+                        Some(scaled) => format_value(scaled, options),
+                        None => missing_cell(options)
+                    };
+                    writer.write_record([format_timestamp(*timestamp, start, options), cleaned_val])?;
+                    *timestamp += *sample_interval;
+                }
+            }
+            if options.flush_every > 0 && (record + 1) % options.flush_every == 0 {
+                for (writer, _, _) in outputs.iter_mut().flatten() {
+                    writer.flush()?;
+                }
+            }
+        }
+    }
+    for (writer, _, _) in outputs.iter_mut().flatten() {
+        writer.flush()?;
+    }
+    if options.validate_timestamps {
+        for (index, (_, _, timestamp)) in outputs.iter().enumerate().filter_map(|(index, output)| Some((index, output.as_ref()?))) {
+            check_end_time(file_path, Some(&data_signals[index].label), *timestamp, start + shift, records_end, record_duration, options)?;
+        }
+    }
+    if let Some(mut events_writer) = events {
+        events_writer.flush()?;
+    }
+    if let Some(stats) = stats {
+        write_stats(file_path, target_dir, order.iter().map(|&index| &stats[index]).collect())?;
+    }
+    if let Some(coverage) = coverage {
+        write_coverage(file_path, target_dir, start, converted_sec, order.iter().map(|&index| &coverage[index]).collect())?;
+    }
+    if let Some(clock) = clock {
+        let columns: Vec<&Signal> = order.iter().map(|&index| data_signals[index]).collect();
+        clock.report(file_path, record_duration, &columns);
+    }
+    Ok(samples_written)
+}
+
+
+// `--format replay`: `<name>.replay.csv` with one `timestamp,signal,value` row
+// per sample, ordered by when it was taken and then by column order. Each
+// signal keeps its own rate, so signals interleave as their sample times
+// fall. A record's samples all lie within the record, so sorting record by
+// record orders the whole file. Returns the number of samples written.
+fn replay_edf(file_path: &Path, target_dir: &Path, options: &Options) -> Result<usize, EdfError> {
+    let mut reader: EdfReader = open_edf(file_path, options)?;
+    let header: EdfHeader = read_prepared_header(file_path, &mut reader, options)?;
+    if options.metadata {
+        write_metadata(file_path, target_dir, &header, options)?;
+    }
+
+    let start: NaiveDateTime = header.start;
+    let record_duration: f64 = header.record_duration;
+    let signals: Vec<Signal> = header.signals;
+    let record_size: usize = signals.iter().map(|s| s.num_samples).sum();
+    let data_signals: Vec<&Signal> = signals.iter().filter(|s| !s.is_annotation()).collect();
+    let order: Vec<usize> = column_order(file_path, &data_signals, record_duration, options);
+    let records: Range<usize> = skip_to_records(file_path, &mut reader, header.num_records, header.data_offset, record_size, options)?;
+
+    let mut labels: Vec<String> = data_signals.iter().map(|s| s.label.clone()).collect();
+    if options.sql_safe_headers {
+        labels = sql_safe_labels(&labels, options.conversion.timestamp_column());
+    }
+    // Position of each data signal's column, or None if it isn't written.
+    let mut positions: Vec<Option<usize>> = vec![None; data_signals.len()];
+    for (position, &index) in order.iter().enumerate() {
+        positions[index] = Some(position);
+    }
+
+    let mut events: Option<Writer<File>> = None;
+    if options.events_csv {
+        events = Some(create_events_writer(file_path, target_dir, options)?);
+    }
+    let mut writer: Writer<File> = csv_writer(&output_path(file_path, target_dir, "replay.csv")?, options)?;
+    if !options.no_header {
+        writer.write_record([options.conversion.timestamp_column(), "signal", "value"])?;
+    }
+
+    let mut stats: Option<Vec<SignalStats>> = None;
+    if options.stats {
+        stats = Some(data_signals.iter().map(|signal| SignalStats::new(signal, record_duration, options)).collect());
+    }
+    let mut coverage: Option<Vec<SignalCoverage>> = None;
+    if options.coverage {
+        coverage = Some(data_signals.iter().map(|signal| SignalCoverage::new(signal, record_duration)).collect());
+    }
+    let converted_sec: f64 = records.len() as f64 * record_duration;
+    let first_record: usize = records.start;
+    let records_end: usize = records.end;
+    let record_ns: f64 = record_duration * 1e9;
+    let mut clock: Option<RecordClock> = options.verbose.then(RecordClock::new);
+    let mut source: RecordSource = RecordSource::new(reader, record_size, records.len(), options);
+    // (offset from the start in ns, column position, index among the data
+    // signals, value) for every sample of the current record.
+    let mut samples: Vec<(i64, usize, usize, Option<f64>)> = Vec::with_capacity(record_size);
+    let mut samples_written: usize = 0;
+    let mut shift: Duration = Duration::zero();
+    let mut gap_filler: GapFiller = GapFiller::new(data_signals.len(), options);
+    // The extra last step releases the records `GapFiller` still holds.
+    for record in records.map(Some).chain([None]) {
+        let ready: Vec<PendingRecord> = match record {
+            Some(record) => {
+                let values: Vec<i16> = read_next_record(&mut source, file_path, record, records_end, header.data_offset, record_size, options)?;
+                if let Some(clock) = clock.as_mut() {
+                    clock.add(time_keeping_onset(&signals, &values, options.conversion.endian()), record_duration);
+                }
+                if record == first_record {
+                    shift = fractional_start(&signals, &values, record, record_duration, options.conversion.endian());
+                }
+                let mut offset: usize = 0;
+                let mut tals: Vec<Tal> = Vec::new();
+                let mut scaled: Vec<Vec<Option<f64>>> = Vec::with_capacity(data_signals.len());
+                for signal in &signals {
+                    let raw_values: &[i16] = &values[offset..offset + signal.num_samples];
+                    offset += signal.num_samples;
+                    if signal.is_annotation() {
+                        if events.is_some() {
+                            tals.extend(parse_tals(&annotation_bytes(raw_values, options.conversion.endian())));
+                        }
+                        continue;
+                    }
+                    // Signals that aren't written aren't scaled either.
+                    if positions[scaled.len()].is_none() {
+                        scaled.push(Vec::new());
+                        continue;
+                    }
+                    if record == first_record {
+                        check_byte_order(file_path, signal, raw_values, options);
+                    }
+                    scaled.push(scale_signal(signal, raw_values, options));
+                }
+
+                if let Some(events_writer) = events.as_mut() {
+                    tals.sort_by(|a, b| a.onset.total_cmp(&b.onset));
+                    write_events(events_writer, start, &tals, options)?;
+                }
+                gap_filler.push(record, values, scaled)
+            },
+            None => gap_filler.finish()
+        };
+
+        for (record, values, scaled) in ready {
+            for ((index, (signal, raw_values)), mut signal_values) in data_samples(&signals, &values).enumerate().zip(scaled) {
+                let Some(position) = positions[index] else {
+                    continue;
+                };
+                if let Some(stats) = stats.as_mut() {
+                    stats[index].add(&signal_values);
+                }
+                blank_non_finite(&mut signal_values);
+                if let Some(coverage) = coverage.as_mut() {
+                    coverage[index].add(signal, record, raw_values, &signal_values);
+                }
+                let sample_ns: f64 = record_ns / signal.num_samples as f64;
+                for (i, value) in signal_values.into_iter().enumerate() {
+                    let offset_ns: i64 = (record as f64 * record_ns + i as f64 * sample_ns).round() as i64;
+                    samples.push((offset_ns, position, index, value));
+                }
+            }
+
+            samples.sort_by_key(|&(offset_ns, position, _, _)| (offset_ns, position));
+            samples_written += samples.len();
+            for (offset_ns, _, index, value) in samples.drain(..) {
+                let cleaned_val: String = match value {
+                    Some(scaled) => format_value(scaled, options),
+                    None => missing_cell(options)
+                };
+                let timestamp: String = format_timestamp(start + shift + Duration::nanoseconds(offset_ns), start, options);
+                writer.write_record([timestamp.as_str(), labels[index].as_str(), cleaned_val.as_str()])?;
+            }
+            if options.flush_every > 0 && (record + 1) % options.flush_every == 0 {
+                writer.flush()?;
+            }
+        }
+    }
+    writer.flush()?;
+    if let Some(mut events_writer) = events {
+        events_writer.flush()?;
+    }
+    if let Some(stats) = stats {
+        write_stats(file_path, target_dir, order.iter().map(|&index| &stats[index]).collect())?;
+    }
+    if let Some(coverage) = coverage {
+        write_coverage(file_path, target_dir, start, converted_sec, order.iter().map(|&index| &coverage[index]).collect())?;
+    }
+    if let Some(clock) = clock {
+        let columns: Vec<&Signal> = order.iter().map(|&index| data_signals[index]).collect();
+        clock.report(file_path, record_duration, &columns);
+    }
+    Ok(samples_written)
+}
+
+
+// Turns labels into file name parts that are valid everywhere: characters
+// other than ASCII letters, digits, '-' and '_' become '_', names Windows
+// reserves get a trailing '_', and labels that end up equal (ignoring case,
+// as Windows does) get "_2", "_3", ... in order.
+fn sanitize_file_labels(labels: &[&str]) -> Vec<String> {
+    const RESERVED: [&str; 22] = [
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+        "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9"
+    ];
+    let mut names: Vec<String> = Vec::with_capacity(labels.len());
+    for label in labels {
+        let mut name: String = label
+            .trim()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        if name.is_empty() {
+            name = "signal".to_string();
+        }
+        if RESERVED.contains(&name.to_ascii_uppercase().as_str()) {
+            name.push('_');
+        }
+        let mut unique: String = name.clone();
+        let mut index: usize = 2;
+        while names.iter().any(|taken| taken.eq_ignore_ascii_case(&unique)) {
+            unique = format!("{}_{}", name, index);
+            index += 1;
+        }
+        names.push(unique);
+    }
+    names
+}
+
+
+// Temporary files that are removed however the conversion ends.
+struct TempFiles(Vec<PathBuf>);
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+
+// `--transpose`: `<name>.transposed.csv` with a row of timestamps and then a
+// row per signal, each led by its label and dimension. A row can only be
+// written once all of its samples are known, so the first pass spills each
+// signal's scaled values to a temporary `<name>.<n>.transpose.tmp` next to
+// the output and the second pass turns those into rows. Memory use stays at
+// about one record however long the recording, for 8 bytes of disk per
+// sample. Returns the number of samples written.
+fn transpose_edf(file_path: &Path, target_dir: &Path, options: &Options) -> Result<usize, EdfError> {
+    let mut reader: EdfReader = open_edf(file_path, options)?;
+    let header: EdfHeader = read_prepared_header(file_path, &mut reader, options)?;
+    if options.metadata {
+        write_metadata(file_path, target_dir, &header, options)?;
+    }
+
+    let start: NaiveDateTime = header.start;
+    let record_duration: f64 = header.record_duration;
+    let signals: Vec<Signal> = header.signals;
+    let record_size: usize = signals.iter().map(|s| s.num_samples).sum();
+    let data_signals: Vec<&Signal> = signals.iter().filter(|s| !s.is_annotation()).collect();
+    let order: Vec<usize> = column_order(file_path, &data_signals, record_duration, options);
+    let columns: Vec<&Signal> = order.iter().map(|&index| data_signals[index]).collect();
+
+    // Every row shares the timestamp row, so the signals have to share a rate.
+    let num_samples: usize = columns.iter().map(|s| s.num_samples).find(|&n| n > 0).unwrap_or(0);
+    if !columns.iter().map(|s| s.num_samples).all(|n| n == 0 || n == num_samples) {
+        let remedy: &str = "or convert without --transpose using --split-signals or --format replay, which keep each signal's own rate";
+        return Err(mismatched_signals(file_path, &columns, remedy));
+    }
+    let records: Range<usize> = skip_to_records(file_path, &mut reader, header.num_records, header.data_offset, record_size, options)?;
+
+    let mut labels: Vec<String> = columns.iter().map(|s| s.label.clone()).collect();
+    if options.sql_safe_headers {
+        labels = sql_safe_labels(&labels, options.conversion.timestamp_column());
+    }
+    let mut positions: Vec<Option<usize>> = vec![None; data_signals.len()];
+    for (position, &index) in order.iter().enumerate() {
+        positions[index] = Some(position);
+    }
+
+    let mut events: Option<Writer<File>> = None;
+    if options.events_csv {
+        events = Some(create_events_writer(file_path, target_dir, options)?);
+    }
+    let mut stats: Option<Vec<SignalStats>> = None;
+    if options.stats {
+        stats = Some(data_signals.iter().map(|signal| SignalStats::new(signal, record_duration, options)).collect());
+    }
+    let mut coverage: Option<Vec<SignalCoverage>> = None;
+    if options.coverage {
+        coverage = Some(data_signals.iter().map(|signal| SignalCoverage::new(signal, record_duration)).collect());
+    }
+    let converted_sec: f64 = records.len() as f64 * record_duration;
+
+    let part_paths: TempFiles = TempFiles(
+        (0..columns.len())
+            .map(|position| output_path(file_path, target_dir, &format!("{}.transpose.tmp", position)))
+            .collect::<Result<Vec<PathBuf>, EdfError>>()?
+    );
+    let mut parts: Vec<BufWriter<File>> = Vec::with_capacity(columns.len());
+    for part_path in &part_paths.0 {
+        parts.push(BufWriter::new(File::create(part_path)?));
+    }
+
+    let first_record: usize = records.start;
+    let records_end: usize = records.end;
+    let num_values: usize = records.len() * num_samples;
+    let mut samples_written: usize = 0;
+    let mut shift: Duration = Duration::zero();
+    let mut clock: Option<RecordClock> = options.verbose.then(RecordClock::new);
+    let mut source: RecordSource = RecordSource::new(reader, record_size, records.len(), options);
+    let mut gap_filler: GapFiller = GapFiller::new(data_signals.len(), options);
+    // The extra last step releases the records `GapFiller` still holds.
+    for record in records.map(Some).chain([None]) {
+        let ready: Vec<PendingRecord> = match record {
+            Some(record) => {
+                let values: Vec<i16> = read_next_record(&mut source, file_path, record, records_end, header.data_offset, record_size, options)?;
+                if let Some(clock) = clock.as_mut() {
+                    clock.add(time_keeping_onset(&signals, &values, options.conversion.endian()), record_duration);
+                }
+                if record == first_record {
+                    shift = fractional_start(&signals, &values, record, record_duration, options.conversion.endian());
+                }
+                let mut offset: usize = 0;
+                let mut tals: Vec<Tal> = Vec::new();
+                let mut scaled: Vec<Vec<Option<f64>>> = Vec::with_capacity(data_signals.len());
+                for signal in &signals {
+                    let raw_values: &[i16] = &values[offset..offset + signal.num_samples];
+                    offset += signal.num_samples;
+                    if signal.is_annotation() {
+                        if events.is_some() {
+                            tals.extend(parse_tals(&annotation_bytes(raw_values, options.conversion.endian())));
+                        }
+                        continue;
+                    }
+                    // Signals that aren't written aren't scaled either.
+                    if positions[scaled.len()].is_none() {
+                        scaled.push(Vec::new());
+                        continue;
+                    }
+                    if record == first_record && signal.num_samples > 0 {
+                        check_byte_order(file_path, signal, raw_values, options);
+                    }
+                    scaled.push(scale_signal(signal, raw_values, options));
+                }
+
+                if let Some(events_writer) = events.as_mut() {
+                    tals.sort_by(|a, b| a.onset.total_cmp(&b.onset));
+                    write_events(events_writer, start, &tals, options)?;
+                }
+                gap_filler.push(record, values, scaled)
+            },
+            None => gap_filler.finish()
+        };
+
+        for (record, values, scaled) in ready {
+            for ((index, (signal, raw_values)), mut signal_values) in data_samples(&signals, &values).enumerate().zip(scaled) {
+                let Some(position) = positions[index] else {
+                    continue;
+                };
+                if signal.num_samples == 0 {
+                    for _ in 0..num_samples {
+                        parts[position].write_all(&f64::NAN.to_le_bytes())?;
+                    }
+                    continue;
+                }
+                if let Some(stats) = stats.as_mut() {
+                    stats[index].add(&signal_values);
+                }
+                // Non-finite values are blanked first, so NaN can stand for missing.
+                blank_non_finite(&mut signal_values);
+                if let Some(coverage) = coverage.as_mut() {
+                    coverage[index].add(signal, record, raw_values, &signal_values);
+                }
+                for value in &signal_values {
+                    parts[position].write_all(&value.unwrap_or(f64::NAN).to_le_bytes())?;
+                }
+                samples_written += signal_values.len();
+            }
+        }
+    }
+
+    let mut writer: Writer<File> = csv_writer(&output_path(file_path, target_dir, "transposed.csv")?, options)?;
+    if !options.no_header {
+        writer.write_field(options.conversion.timestamp_column())?;
+        writer.write_field(timestamp_unit(options))?;
+        let timeline: Timeline = Timeline::new(&columns, record_duration, &options.conversion)?;
+        let mut timestamp: NaiveDateTime = start + shift + records_offset(file_path, &timeline, first_record)?;
+        for _ in 0..num_values {
+            writer.write_field(format_timestamp(timestamp, start, options))?;
+            timestamp += timeline.row_interval();
+        }
+        writer.write_record(None::<&[u8]>)?;
+    }
+    for ((part, part_path), (label, signal)) in parts.into_iter().zip(&part_paths.0).zip(labels.iter().zip(&columns)) {
+        drop(part.into_inner().map_err(|e| e.into_error())?);
+        let mut part_reader: BufReader<File> = BufReader::new(File::open(part_path)?);
+        writer.write_field(label)?;
+        writer.write_field(&signal.dimension)?;
+        let mut bytes: [u8; 8] = [0; 8];
+        for _ in 0..num_values {
+            part_reader.read_exact(&mut bytes)?;
+            let value: f64 = f64::from_le_bytes(bytes);
+            if value.is_nan() {
+                writer.write_field(missing_cell(options))?;
+            } else {
+                writer.write_field(format_value(value, options))?;
+            }
+        }
+        writer.write_record(None::<&[u8]>)?;
+    }
+    writer.flush()?;
+    if let Some(mut events_writer) = events {
+        events_writer.flush()?;
+    }
+    if let Some(stats) = stats {
+        write_stats(file_path, target_dir, order.iter().map(|&index| &stats[index]).collect())?;
+    }
+    if let Some(coverage) = coverage {
+        write_coverage(file_path, target_dir, start, converted_sec, order.iter().map(|&index| &coverage[index]).collect())?;
+    }
+    if let Some(clock) = clock {
+        clock.report(file_path, record_duration, &columns);
+    }
+    Ok(samples_written)
+}
+
+
+// Runs the conversion on a worker thread and stops waiting for it after
+// `timeout`. A blocked read can't be interrupted, so a timed-out worker is
+// told to stop and abandoned; its partial output is removed right away, and
+// again by the worker itself once it stops, in case it wrote more meanwhile.
+fn parse_edf_with_timeout(file_path: &Path, target_dir: &Path, options: &Arc<Options>, timeout: time::Duration) -> Result<usize, EdfError> {
+    let (sender, receiver) = mpsc::channel();
+    let cancel: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    let worker_cancel: Arc<AtomicBool> = Arc::clone(&cancel);
+    let worker_file_path: PathBuf = file_path.to_path_buf();
+    let worker_target_dir: PathBuf = target_dir.to_path_buf();
+    let worker_options: Arc<Options> = Arc::clone(options);
+    thread::spawn(move || {
+        let result: Result<usize, EdfError> = parse_edf(&worker_file_path, &worker_target_dir, &worker_options, None, Some(&worker_cancel));
+        if worker_cancel.load(Ordering::Relaxed) {
+            remove_partial_output(&worker_file_path, &worker_target_dir, &worker_options);
+        }
+        let _ = sender.send(result);
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => {
+            cancel.store(true, Ordering::Relaxed);
+            remove_partial_output(file_path, target_dir, options);
+            let message: String = format!("{}: conversion didn't finish within {:?}", file_path.to_string_lossy(), timeout);
+            Err(EdfError::Timeout(message))
+        },
+        Err(RecvTimeoutError::Disconnected) => {
+            let message: String = format!("{}: conversion stopped without a result", file_path.to_string_lossy());
+            Err(EdfError::Timeout(message))
+        }
+    }
+}
+
+
+// Deletes what a cancelled conversion may have written: its main output and
+// the sidecars the options ask for. Nothing is read from the input, which may
+// be what's hanging, so per-record files are found by name. The SQLite table
+// only appears when its transaction commits, so the database is left alone.
+fn remove_partial_output(file_path: &Path, target_dir: &Path, options: &Options) {
+    let mut outputs: Vec<PathBuf> = Vec::new();
+    if options.per_record_files {
+        let prefix: PathBuf = planned_output_path(file_path, target_dir, "rec");
+        let prefix_name: String = prefix.file_name().unwrap().to_string_lossy().to_string();
+        if let Ok(entries) = fs::read_dir(prefix.parent().unwrap_or(target_dir)) {
+            for entry in entries.flatten() {
+                let name: String = entry.file_name().to_string_lossy().to_string();
+                let index: Option<&str> = name.strip_prefix(&prefix_name).and_then(|rest| rest.strip_suffix(".csv"));
+                if index.is_some_and(|index| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit())) {
+                    outputs.push(entry.path());
+                }
+            }
+        }
+    } else if options.durable {
+        // The CSV from the last run stays until the new one is complete.
+        outputs.push(planned_output_path(file_path, target_dir, "csv.tmp"));
+    } else if options.format != OutputFormat::Sqlite {
+        outputs.extend(planned_outputs(file_path, target_dir, options));
+    }
+    let sidecars: [(bool, &str); 4] = [
+        (options.format == OutputFormat::Npy, "npy.json"),
+        (options.events_csv, "events.csv"),
+        (options.metadata, "metadata.json"),
+        (options.units_sidecar, "units.csv")
+    ];
+    for (_, extension) in sidecars.iter().filter(|(written, _)| *written) {
+        outputs.push(planned_output_path(file_path, target_dir, extension));
+    }
+    for output in outputs {
+        let _ = fs::remove_file(output);
+    }
+}
+
+
+// Range and sample cadence of a converted CSV, gathered in a first pass so the
+// EDF header can be written before the samples.
+struct CsvLayout {
+    labels: Vec<String>,
+    dimensions: Vec<String>,
+    start: NaiveDateTime,
+    // 2, or 3 with a `--rate-header` row.
+    header_rows: usize,
+    interval_ms: i64,
+    num_rows: usize,
+    ranges: Vec<Option<(f64, f64)>>
+}
+
+
+fn open_csv_rows(csv_path: &Path, options: &Options) -> Result<StringRecordsIntoIter<File>, EdfError> {
+    let reader: Reader<File> = ReaderBuilder::new().has_headers(false).delimiter(options.conversion.delimiter()).from_path(csv_path)?;
+    Ok(reader.into_records())
+}
+
+
+fn parse_csv_value(csv_path: &Path, value: &str, options: &Options) -> Result<Option<f64>, EdfError> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    match value.replace(options.decimal, ".").parse::<f64>() {
+        Ok(value) => Ok(Some(value)),
+        Err(_) => Err(EdfError::EdfWrite(format!("{}: '{}' isn't a number", csv_path.to_string_lossy(), value)))
+    }
+}
+
+
+fn read_csv_layout(csv_path: &Path, options: &Options) -> Result<CsvLayout, EdfError> {
+    let mut rows: StringRecordsIntoIter<File> = open_csv_rows(csv_path, options)?;
+    let missing_header = || EdfError::EdfWrite(format!("{}: the two header rows are missing", csv_path.to_string_lossy()));
+    let labels_row: StringRecord = rows.next().ok_or_else(missing_header)??;
+    let dimensions_row: StringRecord = rows.next().ok_or_else(missing_header)??;
+    if labels_row.get(0) != Some(options.conversion.timestamp_column()) {
+        let message: String = format!("{}: the first column must be '{}'; merged CSVs can't be converted back", csv_path.to_string_lossy(), options.conversion.timestamp_column());
+        return Err(EdfError::EdfWrite(message));
+    }
+    let labels: Vec<String> = labels_row.iter().skip(1).map(String::from).collect();
+    let dimensions: Vec<String> = dimensions_row.iter().skip(1).map(String::from).collect();
+
+    let mut header_rows: usize = 2;
+    let mut timestamps: Vec<NaiveDateTime> = Vec::with_capacity(2);
+    let mut num_rows: usize = 0;
+    let mut ranges: Vec<Option<(f64, f64)>> = vec![None; labels.len()];
+    for row in rows {
+        let row: StringRecord = row?;
+        // Only the rate row has no timestamp.
+        if num_rows == 0 && header_rows == 2 && row.get(0) == Some("") {
+            header_rows = 3;
+            continue;
+        }
+        if timestamps.len() < 2 {
+            let timestamp: &str = row.get(0).unwrap_or_default();
+            match NaiveDateTime::parse_from_str(timestamp, options.conversion.datetime_format()) {
+                Ok(timestamp) => timestamps.push(timestamp),
+                Err(_) => return Err(EdfError::Datetime(format!("{}: can't parse timestamp '{}'", csv_path.to_string_lossy(), timestamp)))
+            }
+        }
+        for (range, value) in ranges.iter_mut().zip(row.iter().skip(1)) {
+            if let Some(value) = parse_csv_value(csv_path, value, options)? {
+                *range = match *range {
+                    Some((min, max)) => Some((min.min(value), max.max(value))),
+                    None => Some((value, value))
+                };
+            }
+        }
+        num_rows += 1;
+    }
+
+    let start: NaiveDateTime = match timestamps.first() {
+        Some(&start) => start,
+        None => return Err(EdfError::EdfWrite(format!("{}: there are no samples", csv_path.to_string_lossy())))
+    };
+    // A single row doesn't reveal the rate, so it becomes a 1 Hz signal.
+    let interval_ms: i64 = timestamps.get(1).map_or(1000, |&second| (second - start).num_milliseconds());
+    if interval_ms <= 0 {
+        return Err(EdfError::EdfWrite(format!("{}: timestamps don't increase", csv_path.to_string_lossy())));
+    }
+    Ok(CsvLayout {labels, dimensions, start, header_rows, interval_ms, num_rows, ranges})
+}
+
+
+// The reverse conversion: a CSV written by this tool becomes a plain EDF file.
+// The timestamps only keep whole milliseconds, so the rate is rebuilt from the
+// first interval. Physical bounds are the range of each column, blank cells
+// become the i16::MIN sentinel.
+fn csv_to_edf(csv_path: &Path, target_dir: &Path, options: &Options) -> Result<(), EdfError> {
+    let layout: CsvLayout = read_csv_layout(csv_path, options)?;
+
+    // Records hold at most a second of samples, and as many as evenly divide
+    // the rows so that no padding is needed, which round-trips this tool's
+    // own output exactly.
+    let max_samples_per_record: usize = (1000 / layout.interval_ms).max(1) as usize;
+    let samples_per_record: usize = (1..=max_samples_per_record)
+        .rev()
+        .find(|&n| layout.num_rows.is_multiple_of(n))
+        .unwrap_or(1);
+    let mut signals: Vec<Signal> = Vec::with_capacity(layout.labels.len());
+    for ((label, dimension), range) in layout.labels.iter().zip(&layout.dimensions).zip(&layout.ranges) {
+        let (mut physical_min, mut physical_max): (f64, f64) = range.unwrap_or((0.0, 1.0));
+        if physical_min == physical_max {
+            physical_max = physical_min + 1.0;
+        }
+        // Scale with the bounds as they read back from the header.
+        physical_min = format_header_number(physical_min, 8)?.parse()?;
+        physical_max = format_header_number(physical_max, 8)?.parse()?;
+        signals.push(Signal {
+            label: label.clone(),
+            dimension: dimension.clone(),
+            // i16::MIN stays free as the sentinel for blank cells.
+            bounds: Bounds {digital_min: -32767.0, digital_max: 32767.0, physical_min, physical_max},
+            num_samples: samples_per_record
+        });
+    }
+    let header: EdfHeader = EdfHeader {
+        start: layout.start,
+        patient_info: None,
+        recording_info: None,
+        num_records: layout.num_rows.div_ceil(samples_per_record),
+        record_duration: (samples_per_record as i64 * layout.interval_ms) as f64 / 1000.0,
+        data_offset: 256 * (signals.len() as u64 + 1),
+        signals,
+        warnings: Vec::new()
+    };
+
+    let mut writer: BufWriter<File> = BufWriter::new(File::create(output_path(csv_path, target_dir, "edf")?)?);
+    write_header(&mut writer, &header)?;
+
+    let mut rows = open_csv_rows(csv_path, options)?.skip(layout.header_rows);
+    let mut record: Vec<Vec<i16>> = vec![Vec::with_capacity(samples_per_record); header.signals.len()];
+    for _ in 0..header.num_records {
+        for _ in 0..samples_per_record {
+            // The rows fill whole records, unless the CSV changed since the
+            // first pass; missing rows then become blank samples.
+            let row: Option<StringRecord> = rows.next().transpose()?;
+            for (index, signal) in header.signals.iter().enumerate() {
+                let value: &str = row.as_ref().and_then(|row| row.get(index + 1)).unwrap_or_default();
+                record[index].push(signal.bounds.unscale(parse_csv_value(csv_path, value, options)?));
+            }
+        }
+        for samples in record.iter_mut() {
+            write_record_samples(&mut writer, samples)?;
+            samples.clear();
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+
+// `selftest`: the CSV that converting `SELFTEST_SAMPLES` with the default
+// options has to give, written out by hand rather than by the converter.
+const SELFTEST_CSV: &str = "\
+timestamp,EEG,Resp
+YYYY-MM-DD hh:mm:ss,uV,mV
+2023-01-15T08:30:00.000,0,0
+2023-01-15T08:30:00.250,10,0.5
+2023-01-15T08:30:00.500,-10,1.5
+2023-01-15T08:30:00.750,250,-2.5
+2023-01-15T08:30:01.000,500,10
+2023-01-15T08:30:01.250,-500,99.5
+2023-01-15T08:30:01.500,1000,-99.5
+2023-01-15T08:30:01.750,-1000,
+";
+// Digital samples of the two signals, one record per row. The bounds map
+// them onto values that floating point represents exactly; i16::MIN is a
+// missing sample.
+const SELFTEST_SAMPLES: [[[i16; 4]; 2]; 2] = [
+    [[0, 10, -10, 250], [0, 5, 15, -25]],
+    [[500, -500, 1000, -1000], [100, 995, -995, i16::MIN]]
+];
+
+
+// Writes a small EDF file with known samples to a temporary directory,
+// converts it like any other input and with the library's `edf_to_csv`, and
+// compares both CSVs with `SELFTEST_CSV`. Prints a line per check.
+fn selftest() -> bool {
+    let dir: PathBuf = env::temp_dir().join(format!("edf_to_csv-selftest-{}", process::id()));
+    let result: Result<Vec<(&str, String)>, EdfError> = run_selftest(&dir);
+    let _ = fs::remove_dir_all(&dir);
+    let outputs: Vec<(&str, String)> = match result {
+        Ok(outputs) => outputs,
+        Err(e) => {
+            println!("selftest: FAIL: {}", e);
+            return false;
+        }
+    };
+    let mut passed: bool = true;
+    for (check, output) in outputs {
+        let mismatch: Option<(usize, &str, &str)> = SELFTEST_CSV.lines()
+            .zip(output.lines())
+            .enumerate()
+            .find(|(_, (expected, actual))| expected != actual)
+            .map(|(line, (expected, actual))| (line + 1, expected, actual));
+        match mismatch {
+            None if SELFTEST_CSV.lines().count() == output.lines().count() => println!("selftest: {}: pass", check),
+            None => {
+                passed = false;
+                println!("selftest: {}: FAIL: {} lines instead of {}", check, output.lines().count(), SELFTEST_CSV.lines().count());
+            },
+            Some((line, expected, actual)) => {
+                passed = false;
+                println!("selftest: {}: FAIL: line {} is '{}', expected '{}'", check, line, actual, expected);
+            }
+        }
+    }
+    passed
+}
+
+
+fn run_selftest(dir: &Path) -> Result<Vec<(&'static str, String)>, EdfError> {
+    fs::create_dir_all(dir)?;
+    let edf_path: PathBuf = dir.join("selftest.edf");
+    let signal = |label: &str, dimension: &str, physical: f64| Signal {
+        label: label.to_string(),
+        dimension: dimension.to_string(),
+        bounds: Bounds {digital_min: -1000.0, digital_max: 1000.0, physical_min: -physical, physical_max: physical},
+        num_samples: 4
+    };
+    let header: EdfHeader = EdfHeader {
+        start: NaiveDate::from_ymd_opt(2023, 1, 15).unwrap().and_hms_opt(8, 30, 0).unwrap(),
+        patient_info: None,
+        recording_info: None,
+        num_records: SELFTEST_SAMPLES.len(),
+        record_duration: 1.0,
+        signals: vec![signal("EEG", "uV", 1000.0), signal("Resp", "mV", 100.0)],
+        data_offset: 256 * 3,
+        warnings: Vec::new()
+    };
+    let mut edf: BufWriter<File> = BufWriter::new(File::create(&edf_path)?);
+    write_header(&mut edf, &header)?;
+    for record in SELFTEST_SAMPLES {
+        write_record_samples(&mut edf, &record.concat())?;
+    }
+    edf.flush()?;
+    drop(edf);
+
+    let target_dir: PathBuf = dir.join("out");
+    fs::create_dir_all(&target_dir)?;
+    parse_edf(&edf_path, &target_dir, &Options::default(), None, None)?;
+    let converted: String = fs::read_to_string(target_dir.join("selftest.csv"))?;
+
+    let mut library: Vec<u8> = Vec::new();
+    edf_to_csv::edf_to_csv(BufReader::new(File::open(&edf_path)?), &mut library, &ConversionOptions::default())?;
+    let library: String = String::from_utf8_lossy(&library).into_owned();
+    Ok(vec![("conversion", converted), ("library", library)])
+}
+
+
+// Keeps a copy of everything read through it, so that the header can be
+// parsed and then rewritten without reading it twice.
+struct CopyingReader<'a, R: Read> {
+    reader: &'a mut R,
+    copy: Vec<u8>
+}
+impl<R: Read> Read for CopyingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read: usize = self.reader.read(buf)?;
+        self.copy.extend_from_slice(&buf[..read]);
+        Ok(read)
+    }
+}
+
+
+// `anonymize`: a copy of the EDF file as `<name>.anon.edf` with its
+// identification fields de-identified. Only the header is held in memory; the
+// records are streamed across unchanged.
+fn anonymize_edf(file_path: &Path, target_dir: &Path, options: &Options) -> Result<(), EdfError> {
+    let mut reader: EdfReader = open_edf(file_path, options)?;
+    let mut header_reader: CopyingReader<EdfReader> = CopyingReader { reader: &mut reader, copy: Vec::new() };
+    let header: EdfHeader = read_header_with_fallback_date(&mut header_reader, options.fallback_date)?;
+    let mut header_bytes: Vec<u8> = header_reader.copy;
+    for warning in &header.warnings {
+        warn(file_path, warning);
+    }
+    anonymize_header(&mut header_bytes, &header, options.shift_dates)?;
+
+    let mut writer: BufWriter<File> = BufWriter::new(File::create(output_path(file_path, target_dir, "anon.edf")?)?);
+    writer.write_all(&header_bytes)?;
+    io::copy(&mut reader, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+
+#[derive(Serialize)]
+struct SignalMetadata<'a> {
+    #[serde(flatten)]
+    signal: &'a Signal,
+    sample_rate: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sample_interval_ms: Option<f64>
+}
+
+
+#[derive(Serialize)]
+struct Metadata<'a> {
+    file: String,
+    start: String,
+    num_records: usize,
+    record_duration: f64,
+    patient: Option<&'a PatientInfo>,
+    recording: Option<&'a RecordingInfo>,
+    // With `--fixed-point`, the values are the physical ones times this.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fixed_point_scale: Option<f64>,
+    signals: Vec<SignalMetadata<'a>>
+}
+
+
+// `--stats`: a running summary of the values written for one signal, enough
+// to spot flat, saturated or empty channels without opening the CSV.
+#[derive(Serialize)]
+struct SignalStats {
+    label: String,
+    dimension: String,
+    sample_rate: f64,
+    // The exact interval, which needn't be a whole number.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sample_interval_ms: Option<f64>,
+    count: usize,
+    missing: usize,
+    // Samples whose scaled value was NaN or infinite, written as missing.
+    non_finite: usize,
+    min: Option<f64>,
+    max: Option<f64>,
+    mean: Option<f64>,
+    // Counts in `STATS_BINS` equal bins over the declared physical range, or
+    // the digital one with `--raw`; values outside it count in the end bins.
+    histogram_range: [f64; 2],
+    histogram: Vec<usize>,
+    #[serde(skip)]
+    sum: f64
+}
+impl SignalStats {
+    fn new(signal: &Signal, record_duration: f64, options: &Options) -> Self {
+        let bounds: &Bounds = &signal.bounds;
+        let (low, high): (f64, f64) = if options.conversion.raw_digital() {
+            (bounds.digital_min, bounds.digital_max)
+        } else {
+            (bounds.physical_min, bounds.physical_max)
+        };
+        SignalStats {
+            label: signal.label.clone(),
+            dimension: signal.dimension.clone(),
+            sample_rate: signal.sample_rate(record_duration),
+            sample_interval_ms: Some(signal.sample_interval_ms(record_duration)).filter(|_| signal.num_samples > 0),
+            count: 0,
+            missing: 0,
+            non_finite: 0,
+            min: None,
+            max: None,
+            mean: None,
+            histogram_range: [low.min(high), low.max(high)],
+            histogram: vec![0; STATS_BINS],
+            sum: 0.0
+        }
+    }
+
+    fn add(&mut self, values: &[Option<f64>]) {
+        let [low, high]: [f64; 2] = self.histogram_range;
+        for value in values {
+            let Some(value) = *value else {
+                self.missing += 1;
+                continue;
+            };
+            if !value.is_finite() {
+                self.non_finite += 1;
+                continue;
+            }
+            self.count += 1;
+            self.sum += value;
+            self.min = Some(self.min.map_or(value, |min| min.min(value)));
+            self.max = Some(self.max.map_or(value, |max| max.max(value)));
+            let bin: f64 = if high > low { (value - low) / (high - low) * STATS_BINS as f64 } else { 0.0 };
+            self.histogram[(bin.max(0.0) as usize).min(STATS_BINS - 1)] += 1;
+        }
+        if self.count > 0 {
+            self.mean = Some(self.sum / self.count as f64);
+        }
+    }
+}
+
+
+#[derive(Serialize)]
+struct StatsReport<'a> {
+    file: String,
+    signals: Vec<&'a SignalStats>
+}
+
+
+fn write_stats(file_path: &Path, target_dir: &Path, signals: Vec<&SignalStats>) -> Result<(), EdfError> {
+    let report: StatsReport = StatsReport {file: file_path.to_string_lossy().to_string(), signals};
+    let contents: String = serde_json::to_string_pretty(&report).unwrap();
+    fs::write(output_path(file_path, target_dir, "stats.json")?, contents + "\n")?;
+    Ok(())
+}
+
+
+// A run of consecutive missing or off-scale samples, in seconds from the
+// header's start time; `end_sec` is when the next sample would be taken.
+#[derive(Serialize)]
+struct Gap {
+    start_sec: f64,
+    end_sec: f64,
+    samples: usize
+}
+
+
+// `--coverage`: how much of a signal's converted time holds valid samples, and
+// where the gaps are. Blank, off-scale and non-finite samples count as gaps.
+#[derive(Serialize)]
+struct SignalCoverage {
+    label: String,
+    samples: usize,
+    valid: usize,
+    // `valid / samples`, or null for a signal without samples.
+    coverage: Option<f64>,
+    gaps: Vec<Gap>,
+    #[serde(skip)]
+    sample_interval_sec: f64
+}
+impl SignalCoverage {
+    fn new(signal: &Signal, record_duration: f64) -> Self {
+        SignalCoverage {
+            label: signal.label.clone(),
+            samples: 0,
+            valid: 0,
+            coverage: None,
+            gaps: Vec::new(),
+            sample_interval_sec: record_duration / signal.num_samples.max(1) as f64
+        }
+    }
+
+    fn add(&mut self, signal: &Signal, record: usize, raw_values: &[i16], values: &[Option<f64>]) {
+        let record_start: f64 = record as f64 * signal.num_samples as f64 * self.sample_interval_sec;
+        for (i, (&raw, value)) in raw_values.iter().zip(values).enumerate() {
+            let time: f64 = record_start + i as f64 * self.sample_interval_sec;
+            self.samples += 1;
+            if value.is_some() && !signal.bounds.is_off_scale(raw) {
+                self.valid += 1;
+                continue;
+            }
+            // A gap that ended exactly where this sample starts goes on.
+            match self.gaps.last_mut() {
+                Some(gap) if (gap.end_sec - time).abs() < self.sample_interval_sec / 2.0 => {
+                    gap.end_sec = time + self.sample_interval_sec;
+                    gap.samples += 1;
+                },
+                _ => self.gaps.push(Gap { start_sec: time, end_sec: time + self.sample_interval_sec, samples: 1 })
+            }
+        }
+        self.coverage = Some(self.valid as f64 / self.samples as f64);
+    }
+}
+
+
+#[derive(Serialize)]
+struct CoverageReport<'a> {
+    file: String,
+    start: String,
+    // Length of the converted records.
+    duration_sec: f64,
+    signals: Vec<&'a SignalCoverage>
+}
+
+
+fn write_coverage(file_path: &Path, target_dir: &Path, start: NaiveDateTime, duration_sec: f64, signals: Vec<&SignalCoverage>) -> Result<(), EdfError> {
+    let report: CoverageReport = CoverageReport {
+        file: file_path.to_string_lossy().to_string(),
+        start: start.format(DEFAULT_DATETIME_FORMAT).to_string(),
+        duration_sec,
+        signals
+    };
+    let contents: String = serde_json::to_string_pretty(&report).unwrap();
+    fs::write(output_path(file_path, target_dir, "coverage.json")?, contents + "\n")?;
+    Ok(())
+}
+
+
+fn write_metadata(file_path: &Path, target_dir: &Path, header: &EdfHeader, options: &Options) -> Result<(), EdfError> {
+    let metadata: Metadata = Metadata {
+        file: file_path.to_string_lossy().to_string(),
+        start: header.start.format(DEFAULT_DATETIME_FORMAT).to_string(),
+        num_records: header.num_records,
+        record_duration: header.record_duration,
+        patient: header.patient_info.as_ref(),
+        recording: header.recording_info.as_ref(),
+        fixed_point_scale: options.fixed_point,
+        signals: header.signals
+            .iter()
+            .map(|signal| SignalMetadata {
+                signal,
+                sample_rate: signal.sample_rate(header.record_duration),
+                sample_interval_ms: Some(signal.sample_interval_ms(header.record_duration)).filter(|_| signal.num_samples > 0)
+            })
+            .collect()
+    };
+    let contents: String = serde_json::to_string_pretty(&metadata).unwrap();
+    fs::write(output_path(file_path, target_dir, "metadata.json")?, contents + "\n")?;
+    Ok(())
+}
+
+
+#[derive(Serialize)]
+struct SchemaColumn {
+    name: String,
+    #[serde(rename = "type")]
+    data_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sample_rate: Option<f64>
+}
+
+
+#[derive(Serialize)]
+struct Schema {
+    table: String,
+    start: String,
+    num_records: usize,
+    record_duration: f64,
+    columns: Vec<SchemaColumn>
+}
+
+
+// The signals `--expect-schema` requires. A `<name>.schema.json` written by
+// `--schema-only` can be used as is; the timestamp column and the other fields
+// are ignored, and a missing unit or rate isn't checked.
+#[derive(Deserialize)]
+struct ExpectedSchema {
+    columns: Vec<ExpectedColumn>
+}
+
+
+#[derive(Deserialize)]
+struct ExpectedColumn {
+    name: String,
+    #[serde(rename = "type")]
+    data_type: Option<String>,
+    unit: Option<String>,
+    sample_rate: Option<f64>
+}
+
+
+// `--channels-from-edf`: the data signal labels of a reference file, in its
+// order, after the label options.
+fn read_reference_labels(reference: &Path, options: &Options) -> Result<Vec<String>, EdfError> {
+    let invalid = |e: EdfError| EdfError::InvalidArgument(format!("{}: {}", reference.to_string_lossy(), e));
+    let mut reader: EdfReader = open_edf(reference, options).map_err(invalid)?;
+    let mut header: EdfHeader = read_edf_header(&mut reader, options).map_err(invalid)?;
+    process_labels(reference, &mut header.signals, options);
+    Ok(header.signals.into_iter().filter(|s| !s.is_annotation()).map(|s| s.label).collect())
+}
+
+
+fn read_expected_schema(schema_path: &Path) -> Result<ExpectedSchema, EdfError> {
+    let contents: String = match fs::read_to_string(schema_path) {
+        Ok(contents) => contents,
+        Err(e) => return Err(EdfError::InvalidArgument(format!("{}: {}", schema_path.to_string_lossy(), e)))
+    };
+    let mut schema: ExpectedSchema = match serde_json::from_str(&contents) {
+        Ok(schema) => schema,
+        Err(e) => return Err(EdfError::InvalidArgument(format!("{}: {}", schema_path.to_string_lossy(), e)))
+    };
+    schema.columns.retain(|column| column.data_type.as_deref() != Some("timestamp"));
+    Ok(schema)
+}
+
+
+// Lists every way the file's signals differ from the expected schema.
+fn check_expected_schema(file_path: &Path, header: &EdfHeader, expected: &ExpectedSchema) -> Result<(), EdfError> {
+    let signals: Vec<&Signal> = header.signals.iter().filter(|s| !s.is_annotation()).collect();
+    let mut mismatches: Vec<String> = Vec::new();
+    for column in &expected.columns {
+        let Some(signal) = signals.iter().find(|s| s.label == column.name) else {
+            mismatches.push(format!("missing signal '{}'", column.name));
+            continue;
+        };
+        if let Some(unit) = &column.unit {
+            if signal.dimension != *unit {
+                mismatches.push(format!("signal '{}' is in '{}', expected '{}'", signal.label, signal.dimension, unit));
+            }
+        }
+        if let Some(sample_rate) = column.sample_rate {
+            let actual: f64 = signal.sample_rate(header.record_duration);
+            if (actual - sample_rate).abs() > 1e-6 {
+                mismatches.push(format!("signal '{}' has {} Hz, expected {} Hz", signal.label, actual, sample_rate));
+            }
+        }
+    }
+    for signal in &signals {
+        if !expected.columns.iter().any(|column| column.name == signal.label) {
+            mismatches.push(format!("unexpected signal '{}'", signal.label));
+        }
+    }
+
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+    for mismatch in &mismatches {
+        warn(file_path, mismatch);
+    }
+    Err(EdfError::UnexpectedSchema(mismatches.join("; ")))
+}
+
+
+fn build_schema(file_path: &Path, header: &EdfHeader, options: &Options) -> Schema {
+    let data_signals: Vec<&Signal> = header.signals.iter().filter(|s| !s.is_annotation()).collect();
+    let signals: Vec<&Signal> = column_order(file_path, &data_signals, header.record_duration, options)
+        .into_iter()
+        .map(|index| data_signals[index])
+        .collect();
+    let mut labels: Vec<String> = signals.iter().map(|s| s.label.clone()).collect();
+    if options.sql_safe_headers {
+        labels = sql_safe_labels(&labels, options.conversion.timestamp_column());
+    }
+
+    let mut columns: Vec<SchemaColumn> = Vec::with_capacity(1 + labels.len());
+    columns.push(SchemaColumn {
+        name: options.conversion.timestamp_column().to_string(),
+        data_type: "timestamp",
+        unit: None,
+        sample_rate: None
+    });
+    for (signal, label) in signals.iter().zip(labels) {
+        columns.push(SchemaColumn {
+            name: label,
+            data_type: "float",
+            unit: Some(signal.dimension.clone()),
+            sample_rate: Some(signal.sample_rate(header.record_duration))
+        });
+    }
+
+    Schema {
+        table: file_path.file_stem().unwrap().to_string_lossy().to_string(),
+        start: header.start.format(DEFAULT_DATETIME_FORMAT).to_string(),
+        num_records: header.num_records,
+        record_duration: header.record_duration,
+        columns
+    }
+}
+
+
+fn quote_sql_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+
+fn schema_to_sql(schema: &Schema) -> String {
+    let mut sql: String = format!("CREATE TABLE {} (\n", quote_sql_identifier(&schema.table));
+    let definitions: Vec<String> = schema.columns
+        .iter()
+        .map(|column| {
+            let sql_type: &str = match column.data_type {
+                "timestamp" => "TIMESTAMP",
+                _ => "DOUBLE PRECISION"
+            };
+            format!("    {} {}", quote_sql_identifier(&column.name), sql_type)
+        })
+        .collect();
+    sql.push_str(&definitions.join(",\n"));
+    sql.push_str("\n);\n");
+    sql
+}
+
+
+// Writes `<name>.schema.json` or `<name>.schema.sql` from the header alone,
+// without reading any data records.
+fn write_schema(file_path: &Path, target_dir: &Path, options: &Options) -> Result<(), EdfError> {
+    let mut reader: EdfReader = open_edf(file_path, options)?;
+    let mut header: EdfHeader = read_edf_header(&mut reader, options)?;
+    for warning in &header.warnings {
+        warn(file_path, warning);
+    }
+    process_labels(file_path, &mut header.signals, options);
+    if let Some(expected) = &options.expect_schema {
+        check_expected_schema(file_path, &header, expected)?;
+    }
+
+    let schema: Schema = build_schema(file_path, &header, options);
+    match options.schema_format {
+        SchemaFormat::Json => {
+            let contents: String = serde_json::to_string_pretty(&schema).unwrap();
+            fs::write(output_path(file_path, target_dir, "schema.json")?, contents + "\n")?;
+        },
+        SchemaFormat::Sql => {
+            fs::write(output_path(file_path, target_dir, "schema.sql")?, schema_to_sql(&schema))?;
+        }
+    }
+    Ok(())
+}
+
+
+fn read_edf_header(reader: &mut EdfReader, options: &Options) -> Result<EdfHeader, EdfError> {
+    let mut header: EdfHeader = read_header_with_fallback_date(reader, options.fallback_date)?;
+    if options.warn_missing_units {
+        header.warnings.extend(check_missing_units(&header.signals));
+    }
+    if let Some(placeholder) = &options.unit_placeholder {
+        for signal in header.signals.iter_mut().filter(|signal| !signal.is_annotation() && signal.dimension.is_empty()) {
+            signal.dimension = placeholder.clone();
+        }
+    }
+    if let Some(target) = &options.target_unit {
+        let unconverted: Vec<String> = convert_signal_units(&mut header.signals, target);
+        if !unconverted.is_empty() {
+            let message: String = format!("signals {} have no dimension convertible to '{}'", unconverted.join(", "), target);
+            if options.strict {
+                return Err(EdfError::UnitConversion(message));
+            }
+            header.warnings.push(format!("{}; they keep their own units", message));
+        }
+    }
+    if options.check_units {
+        header.warnings.extend(check_unit_prefixes(&header.signals));
+    }
+    Ok(header)
+}
+
+
+fn warn(file_path: &Path, message: &str) {
+    eprintln!("warning: {}: {}", file_path.to_string_lossy(), message);
+}
+
+
+fn list_edf(file_path: &Path, options: &Options) -> Result<(), EdfError> {
+    let mut reader: EdfReader = open_edf(file_path, options)?;
+    let header: EdfHeader = read_edf_header(&mut reader, options)?;
+    for warning in &header.warnings {
+        warn(file_path, warning);
+    }
+
+    println!("{}", file_path.to_string_lossy());
+    println!("  start: {}", header.start.format(DEFAULT_DATETIME_FORMAT));
+    if let Some(info) = &header.patient_info {
+        println!("  patient: code {}, sex {}, birthdate {}, name {}", info.code, info.sex, info.birthdate, info.name);
+    }
+    if let Some(info) = &header.recording_info {
+        println!("  recording: startdate {}, admin code {}, technician {}, equipment {}", info.startdate, info.admin_code, info.technician, info.equipment);
+        if !info.additional.is_empty() {
+            println!("  recording additional: {}", info.additional);
+        }
+    }
+    println!("  records: {} x {} s", header.num_records, header.record_duration);
+    println!("  signals: {}", header.num_signals());
+    for signal in &header.signals {
+        let mut interval: String = String::new();
+        if signal.num_samples > 0 {
+            interval = format!(", {} ms/sample", signal.sample_interval_ms(header.record_duration));
+        }
+        println!(
+            "    {} [{}]: {} samples/record, {} Hz{}",
+            signal.label,
+            signal.dimension,
+            signal.num_samples,
+            signal.sample_rate(header.record_duration),
+            interval
+        );
+    }
+    Ok(())
+}
+
+
+fn is_edf_file(file_path: &Path) -> bool {
+    file_path.is_file() && file_path.extension().is_some_and(|extension| extension == "edf")
+}
+
+
+// The last path segment of a URL, without its query or fragment.
+fn url_file_name(url: &Path) -> &OsStr {
+    let url: &str = url.to_str().unwrap_or_default();
+    let path: &str = url.split(['?', '#']).next().unwrap_or_default();
+    OsStr::new(path.rsplit('/').next().filter(|name| !name.is_empty()).unwrap_or("download"))
+}
+
+
+// A file that can be reopened and read from any position: not stdin, a URL
+// or a ZIP entry.
+fn is_local_file(file_path: &Path) -> bool {
+    file_path != Path::new(STDIN_PATH) && !is_url(file_path) && split_zip_path(file_path).is_none()
+}
+
+
+fn is_url(file_path: &Path) -> bool {
+    let path: Cow<str> = file_path.to_string_lossy();
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+
+fn is_zip_file(file_path: &Path) -> bool {
+    file_path.is_file() && file_path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+
+// Lists the EDF entries of a ZIP archive as `archive.zip/inner/path.edf`.
+// Entries whose names would escape the archive's directory are skipped.
+fn list_zip_edf_files(archive_path: &Path) -> Result<Vec<PathBuf>, EdfError> {
+    let mut archive: ZipArchive<File> = ZipArchive::new(File::open(archive_path)?)?;
+    let mut edf_list: Vec<PathBuf> = Vec::new();
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)?;
+        if let Some(entry_path) = entry.enclosed_name() {
+            if entry.is_file() && entry_path.extension().is_some_and(|extension| extension == "edf") {
+                edf_list.push(archive_path.join(entry_path));
+            }
+        }
+    }
+    Ok(edf_list)
+}
+
+
+// Symlinked directories are skipped unless `--follow-symlinks` is given; then
+// every directory is entered at most once by its canonical path, so a link
+// back to an ancestor can't recurse forever.
+fn list_edf_files(dir_path: &Path, options: &Options) -> Vec<PathBuf> {
+    let mut visited: Vec<PathBuf> = Vec::new();
+    collect_edf_files(dir_path, options, &mut visited)
+}
+
+
+fn collect_edf_files(dir_path: &Path, options: &Options, visited: &mut Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut edf_list: Vec<PathBuf> = Vec::new();
+    if let Ok(canonical) = fs::canonicalize(dir_path) {
+        if visited.contains(&canonical) {
+            return edf_list;
+        }
+        visited.push(canonical);
+    }
+
+    // An unreadable or vanished directory is skipped, not fatal to the run.
+    let dir_contents: fs::ReadDir = match fs::read_dir(dir_path) {
+        Ok(dir_contents) => dir_contents,
+        Err(e) => {
+            warn(dir_path, &format!("skipping directory: {}", e));
+            return edf_list;
+        }
+    };
+
+    for entry in dir_contents {
+        let file_path: PathBuf = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                warn(dir_path, &format!("skipping an entry: {}", e));
+                continue;
+            }
+        };
+        if is_edf_file(&file_path) {
+            edf_list.push(file_path)
+        } else if file_path.is_dir() {
+            let is_symlink: bool = fs::symlink_metadata(&file_path).map(|m| m.file_type().is_symlink()).unwrap_or(false);
+            if !is_symlink || options.follow_symlinks {
+                edf_list.extend(collect_edf_files(&file_path, options, visited))
+            }
+        }
+    }
+    edf_list
+}
+
+
+fn parse_glob(value: &str) -> Result<Pattern, EdfError> {
+    Pattern::new(value).map_err(|e| EdfError::InvalidArgument(format!("invalid glob '{}': {}", value, e)))
+}
+
+
+// `--include-glob`/`--exclude-glob` match the file name only, so "*night*"
+// selects files wherever they are found. Excludes win over includes.
+fn selected_by_globs(file_path: &Path, options: &Options) -> bool {
+    let Some(file_name) = file_path.file_name().and_then(OsStr::to_str) else {
+        return options.include_globs.is_empty();
+    };
+    if options.exclude_globs.iter().any(|pattern| pattern.matches(file_name)) {
+        return false;
+    }
+    options.include_globs.is_empty() || options.include_globs.iter().any(|pattern| pattern.matches(file_name))
+}
+
+
+// Creates the output directory and checks that files can be created in it,
+// so that an unwritable one fails once instead of once per input file.
+fn prepare_output_dir(target_dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(target_dir)?;
+    let probe_path: PathBuf = target_dir.join(format!(".write-probe-{}", process::id()));
+    File::create(&probe_path)?;
+    fs::remove_file(&probe_path)
+}
+
+
+fn get_status_logger() -> Writer<File> {
+    let status_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open("status.txt")
+        .unwrap();
+    
+    WriterBuilder::new()
+        .delimiter(b':')
+        .quote_style(QuoteStyle::Always)
+        .from_writer(status_file)
+}
+
+
+// Appends one line to the status log, stamped with the local time like the
+// rest of the log.
+fn log_status(status_logger: &mut Writer<File>, path: &Path, message: &str) {
+    let timestamp: String = Local::now().naive_local().format(DEFAULT_DATETIME_FORMAT).to_string();
+    status_logger.write_record([&timestamp, path.to_str().unwrap(), message]).unwrap();
+}
+
+
+// Reads input paths from a list file, one per line. Blank lines and lines
+// starting with '#' are ignored.
+fn read_file_list(list_path: &Path) -> Result<Vec<String>, EdfError> {
+    let contents: String = fs::read_to_string(list_path)?;
+    Ok(contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect())
+}
+
+
+// Reads `old,new` label pairs; an optional "old,new" header row is skipped.
+fn read_rename_map(map_path: &Path) -> Result<Vec<(String, String)>, EdfError> {
+    let invalid = |e: csv::Error| EdfError::InvalidArgument(format!("{}: {}", map_path.to_string_lossy(), e));
+    let mut reader: Reader<File> = ReaderBuilder::new().has_headers(false).from_path(map_path).map_err(invalid)?;
+    let mut rename: Vec<(String, String)> = Vec::new();
+    for record in reader.records() {
+        let record: StringRecord = record.map_err(invalid)?;
+        let (Some(old), Some(new)) = (record.get(0), record.get(1)) else {
+            let message: String = format!("{}: line {} isn't an old,new pair", map_path.to_string_lossy(), rename.len() + 1);
+            return Err(EdfError::InvalidArgument(message));
+        };
+        let (old, new): (&str, &str) = (old.trim(), new.trim());
+        if rename.is_empty() && old == "old" && new == "new" {
+            continue;
+        }
+        rename.push((old.to_string(), new.to_string()));
+    }
+    Ok(rename)
+}
+
+
+// "first..end", "first..=last" or "first.." as in Rust, all zero-based.
+fn parse_record_range(value: &str) -> Result<(usize, Option<usize>), EdfError> {
+    let invalid = || EdfError::InvalidArgument(format!("can't parse '{}' as a record range like 100..110", value));
+    let (first, end) = value.split_once("..").ok_or_else(invalid)?;
+    let first: usize = first.parse().map_err(|_| invalid())?;
+    let end: Option<usize> = match end.strip_prefix('=') {
+        Some(last) => Some(last.parse::<usize>().map_err(|_| invalid())? + 1),
+        None if end.is_empty() => None,
+        None => Some(end.parse().map_err(|_| invalid())?)
+    };
+    Ok((first, end))
+}
+
+
+// Accepts an ISO datetime with an offset ("2023-01-15T08:30:00+01:00"),
+// without one ("2023-01-15T08:30:00") or a plain date, which means midnight
+// at the start of that day. Like EDF start times, the last two are local time.
+fn parse_since(value: &str) -> Result<DateTime<FixedOffset>, EdfError> {
+    let invalid = || EdfError::InvalidArgument(format!("can't parse '{}' as a date or datetime", value));
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Ok(datetime);
+    }
+    let naive: NaiveDateTime = match value.parse::<NaiveDateTime>() {
+        Ok(datetime) => datetime,
+        Err(_) => value.parse::<NaiveDate>().map_err(|_| invalid())?.and_hms_opt(0, 0, 0).unwrap()
+    };
+    // A time skipped by a DST change doesn't exist; one repeated by it is
+    // read as the earlier of the two.
+    match Local.from_local_datetime(&naive).earliest() {
+        Some(datetime) => Ok(datetime.fixed_offset()),
+        None => Err(EdfError::InvalidArgument(format!("'{}' doesn't exist in the local time zone", value)))
+    }
+}
+
+
+// Files whose modification time can't be read are kept so that the
+// conversion reports the underlying error.
+fn modified_since(file_path: &Path, since: DateTime<FixedOffset>) -> bool {
+    match fs::metadata(file_path).and_then(|m| m.modified()) {
+        Ok(modified) => DateTime::<Utc>::from(modified) > since,
+        Err(_) => true
+    }
+}
+
+
+// Sorting is stable, so files that tie (or whose start time can't be read,
+// which sort last) keep their path order.
+fn sort_files(edf_file_paths: &mut [PathBuf], options: &Options) {
+    if options.sort == SortOrder::None {
+        return;
+    }
+    edf_file_paths.sort();
+    match options.sort {
+        SortOrder::StartTime => {
+            edf_file_paths.sort_by_cached_key(|file_path| {
+                let start: Option<NaiveDateTime> = open_edf(file_path, options)
+                    .and_then(|mut reader| read_edf_header(&mut reader, options))
+                    .ok()
+                    .map(|header| header.start);
+                (start.is_none(), start)
+            });
+        },
+        SortOrder::Size => {
+            edf_file_paths.sort_by_cached_key(|file_path| fs::metadata(file_path).map(|m| m.len()).unwrap_or(0));
+        },
+        SortOrder::None | SortOrder::Path => {}
+    }
+}
+
+
+fn next_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, EdfError> {
+    args.next().ok_or_else(|| EdfError::InvalidArgument(format!("{} requires a value", flag)))
+}
+
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<(Options, Vec<String>), EdfError> {
+    let mut options: Options = Options::default();
+    let mut conversion: ConversionOptionsBuilder = ConversionOptions::builder();
+    let mut inputs: Vec<String> = Vec::new();
+
+    let mut rfc3339: bool = false;
+    let mut compress_level_given: bool = false;
+    let mut target_unit_given: bool = false;
+    let mut utc_offset: Option<String> = None;
+    let mut channels_from: Option<PathBuf> = None;
+
+    // A profile only changes the defaults, so it is applied before any flag
+    // wherever it appears on the command line.
+    let args: Vec<String> = args.collect();
+    if let Some(position) = args.iter().rposition(|arg| arg == "--profile") {
+        match args.get(position + 1) {
+            Some(value) => Profile::parse(value)?.apply(&mut options, &mut conversion),
+            None => return Err(EdfError::InvalidArgument("--profile needs a value".to_string()))
+        }
+    }
+
+    let mut args = args.into_iter().peekable();
+    match args.peek().map(String::as_str) {
+        Some("verify") => {
+            args.next();
+            options.verify = true;
+        },
+        Some("to-edf") => {
+            args.next();
+            options.to_edf = true;
+        },
+        Some("anonymize") => {
+            args.next();
+            options.anonymize = true;
+        },
+        Some("selftest") => {
+            args.next();
+            options.selftest = true;
+        },
+        _ => {}
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--interpolate" => {
+                options.interpolation = match next_value(&mut args, &arg)?.as_str() {
+                    "none" => Interpolation::None,
+                    "linear" => Interpolation::Linear,
+                    other => return Err(EdfError::InvalidArgument(format!("unknown interpolation mode '{}'", other)))
+                };
+            },
+            "--max-gap" => {
+                options.max_gap = next_value(&mut args, &arg)?.parse()?;
+            },
+            "--list" => {
+                options.list = true;
+            },
+            "--dry-run-list" => {
+                options.dry_run_list = true;
+            },
+            "--verbose" => {
+                options.verbose = true;
+            },
+            "--transpose" => {
+                options.transpose = true;
+            },
+            "--sample-offset-column" => {
+                options.sample_offset_column = true;
+            },
+            "--sparse-timestamps" => {
+                options.sparse_timestamps = true;
+            },
+            "--quote" => {
+                options.quote_style = match next_value(&mut args, &arg)?.as_str() {
+                    "always" => QuoteStyle::Always,
+                    "necessary" => QuoteStyle::Necessary,
+                    "never" => QuoteStyle::Never,
+                    other => return Err(EdfError::InvalidArgument(format!("unknown quote style '{}'", other)))
+                };
+            },
+            "--gzip" => {
+                options.gzip = true;
+            },
+            "--compress-level" => {
+                let value: String = next_value(&mut args, &arg)?;
+                options.compress_level = match value.parse::<u32>() {
+                    Ok(level) if level <= 9 => level,
+                    _ => return Err(EdfError::InvalidArgument(format!("--compress-level must be 0 to 9, not '{}'", value)))
+                };
+                compress_level_given = true;
+            },
+            "--on-duplicate-timestamp" => {
+                options.on_duplicate_timestamp = DuplicateTimestamps::parse(&next_value(&mut args, &arg)?)?;
+            },
+            "--no-header" => {
+                options.no_header = true;
+            },
+            "--drop-empty-signals" => {
+                options.drop_empty_signals = true;
+            },
+            "--channels-from-edf" => {
+                channels_from = Some(PathBuf::from(next_value(&mut args, &arg)?));
+            },
+            "--columns-order" => {
+                let value: String = next_value(&mut args, &arg)?;
+                options.columns_order = Some(value.split(',').map(|label| label.trim().to_string()).collect());
+            },
+            "--drop-unlisted" => {
+                options.drop_unlisted = true;
+            },
+            "--low-memory" => {
+                options.low_memory = true;
+            },
+            "--records" => {
+                options.records = Some(parse_record_range(&next_value(&mut args, &arg)?)?);
+            },
+            "--record-stride" => {
+                options.record_stride = next_value(&mut args, &arg)?.parse()?;
+                if options.record_stride == 0 {
+                    return Err(EdfError::InvalidArgument("--record-stride must be at least 1".to_string()));
+                }
+            },
+            "--limit-rows" => {
+                options.limit_rows = Some(next_value(&mut args, &arg)?.parse()?);
+            },
+            "--rate-header" => {
+                options.rate_header = true;
+            },
+            "--units-sidecar" => {
+                options.units_sidecar = true;
+            },
+            "--check-units" => {
+                options.check_units = true;
+            },
+            "--warn-missing-units" => {
+                options.warn_missing_units = true;
+            },
+            "--unit-placeholder" => {
+                options.unit_placeholder = Some(next_value(&mut args, &arg)?);
+            },
+            "--target-unit" => {
+                let unit: String = next_value(&mut args, &arg)?;
+                if unit_conversion(&unit, &unit).is_none() {
+                    return Err(EdfError::InvalidArgument(format!("unknown unit '{}'", unit)));
+                }
+                options.target_unit = Some(unit);
+                target_unit_given = true;
+            },
+            "--timeline-signal" => {
+                let label: String = next_value(&mut args, &arg)?;
+                conversion.timeline_signal((label != "highest").then_some(label.as_str()));
+            },
+            "--json-errors" => {
+                options.json_errors = true;
+            },
+            "--sql-safe-headers" => {
+                options.sql_safe_headers = true;
+            },
+            "--flush-every" => {
+                options.flush_every = next_value(&mut args, &arg)?.parse()?;
+            },
+            "--shift-dates" => {
+                options.shift_dates = Some(next_value(&mut args, &arg)?.parse()?);
+            },
+            "--durable" => {
+                options.durable = true;
+            },
+            "--per-record-files" => {
+                options.per_record_files = true;
+            },
+            "--inline-metadata" => {
+                let value: String = next_value(&mut args, &arg)?;
+                let (column, field): (&str, &str) = value.split_once('=').unwrap_or((&value, &value));
+                options.inline_metadata.push((column.to_string(), MetadataField::parse(field)?));
+            },
+            "--schema-only" => {
+                options.schema_only = true;
+            },
+            "--rename" => {
+                options.rename = read_rename_map(Path::new(&next_value(&mut args, &arg)?))?;
+            },
+            "--precision" => {
+                options.precision = Some(next_value(&mut args, &arg)?.parse()?);
+            },
+            "--fixed-point" => {
+                let scale: f64 = next_value(&mut args, &arg)?.parse()?;
+                if !(scale.is_finite() && scale > 0.0) {
+                    return Err(EdfError::InvalidArgument("--fixed-point needs a positive scale".to_string()));
+                }
+                options.fixed_point = Some(scale);
+            },
+            "--rounding" => {
+                options.rounding = match next_value(&mut args, &arg)?.as_str() {
+                    "half-even" => Rounding::HalfEven,
+                    "half-up" => Rounding::HalfUp,
+                    "truncate" => Rounding::Truncate,
+                    other => return Err(EdfError::InvalidArgument(format!("unknown rounding mode '{}'", other)))
+                };
+            },
+            "--expect-schema" => {
+                options.expect_schema = Some(read_expected_schema(Path::new(&next_value(&mut args, &arg)?))?);
+            },
+            "--follow-symlinks" => {
+                options.follow_symlinks = true;
+            },
+            "--format" => {
+                options.format = match next_value(&mut args, &arg)?.as_str() {
+                    "csv" => OutputFormat::Csv,
+                    "npy" => OutputFormat::Npy,
+                    "sqlite" => OutputFormat::Sqlite,
+                    "replay" => OutputFormat::Replay,
+                    other => return Err(EdfError::InvalidArgument(format!("unknown output format '{}'", other)))
+                };
+            },
+            "--schema-format" => {
+                options.schema_format = match next_value(&mut args, &arg)?.as_str() {
+                    "json" => SchemaFormat::Json,
+                    "sql" => SchemaFormat::Sql,
+                    other => return Err(EdfError::InvalidArgument(format!("unknown schema format '{}'", other)))
+                };
+            },
+            "--strip-suffix" => {
+                options.strip_suffix = Some(next_value(&mut args, &arg)?);
+            },
+            "--since" => {
+                options.since = Some(parse_since(&next_value(&mut args, &arg)?)?);
+            },
+            "--include-glob" => {
+                options.include_globs.push(parse_glob(&next_value(&mut args, &arg)?)?);
+            },
+            "--exclude-glob" => {
+                options.exclude_globs.push(parse_glob(&next_value(&mut args, &arg)?)?);
+            },
+            "--metadata" => {
+                options.metadata = true;
+            },
+            "--events-csv" => {
+                options.events_csv = true;
+            },
+            "--ignore-bad-date" => {
+                if options.fallback_date.is_none() {
+                    options.fallback_date = Some(NaiveDate::from_ymd_opt(1970, 1, 1).unwrap());
+                }
+            },
+            "--default-date" => {
+                let value: String = next_value(&mut args, &arg)?;
+                match value.parse::<NaiveDate>() {
+                    Ok(date) => options.fallback_date = Some(date),
+                    Err(_) => return Err(EdfError::InvalidArgument(format!("can't parse '{}' as a YYYY-MM-DD date", value)))
+                }
+            },
+            "--merge-to" => {
+                options.merge_to = Some(PathBuf::from(next_value(&mut args, &arg)?));
+            },
+            "--datetime-format" => {
+                let datetime_format: String = next_value(&mut args, &arg)?;
+                // Offset specifiers like %z parse but can't format a naive
+                // datetime, so try the format on a sample now.
+                let mut sample: String = String::new();
+                if fmt::write(&mut sample, format_args!("{}", NaiveDateTime::default().format(&datetime_format))).is_err() {
+                    return Err(EdfError::InvalidArgument(format!("invalid datetime format '{}'", datetime_format)));
+                }
+                conversion.datetime_format(&datetime_format);
+            },
+            "--profile" => {
+                next_value(&mut args, &arg)?;
+            },
+            "--elapsed-seconds" => {
+                options.time_column = TimeColumn::Elapsed;
+            },
+            "--datetime-column" => {
+                options.time_column = TimeColumn::Datetime;
+            },
+            "--time-format" => {
+                options.time_column = TimeColumn::parse(&next_value(&mut args, &arg)?)?;
+            },
+            "--raw" => {
+                conversion.raw_digital(true);
+            },
+            "--scaled" => {
+                conversion.raw_digital(false);
+            },
+            "--missing-value" => {
+                conversion.missing_value(&next_value(&mut args, &arg)?);
+            },
+            "--min-rate" => {
+                options.min_rate = Some(next_value(&mut args, &arg)?.parse()?);
+            },
+            "--max-rate" => {
+                options.max_rate = Some(next_value(&mut args, &arg)?.parse()?);
+            },
+            "--prefetch" => {
+                options.prefetch = next_value(&mut args, &arg)?.parse()?;
+            },
+            "--timestamp-column-name" => {
+                let name: String = next_value(&mut args, &arg)?;
+                if name.trim().is_empty() {
+                    return Err(EdfError::InvalidArgument("--timestamp-column-name can't be empty".to_string()));
+                }
+                conversion.timestamp_column(&name);
+            },
+            "--delimiter" => {
+                let value: String = next_value(&mut args, &arg)?;
+                conversion.delimiter(match value.as_str() {
+                    "tab" | "\\t" => b'\t',
+                    _ if value.len() == 1 && value.is_ascii() => value.as_bytes()[0],
+                    _ => return Err(EdfError::InvalidArgument(format!("the delimiter must be a single ASCII character or 'tab', not '{}'", value)))
+                });
+            },
+            "--decimal" => {
+                options.decimal = match next_value(&mut args, &arg)?.as_str() {
+                    "." => '.',
+                    "," => ',',
+                    other => return Err(EdfError::InvalidArgument(format!("the decimal separator must be '.' or ',', not '{}'", other)))
+                };
+            },
+            "--skip-corrupt" => {
+                options.skip_corrupt = true;
+            },
+            "--validate-timestamps" => {
+                options.validate_timestamps = true;
+            },
+            "--strict" => {
+                options.strict = true;
+            },
+            "--db" => {
+                options.db = Some(PathBuf::from(next_value(&mut args, &arg)?));
+            },
+            "--override-bounds" => {
+                options.override_bounds.push(BoundsOverride::parse(&next_value(&mut args, &arg)?)?);
+            },
+            "--stats" => {
+                options.stats = true;
+            },
+            "--coverage" => {
+                options.coverage = true;
+            },
+            "--standard-texts" => {
+                options.standard_texts = true;
+            },
+            "--split-signals" => {
+                options.split_signals = true;
+            },
+            "--endian" => {
+                conversion.endian(match next_value(&mut args, &arg)?.as_str() {
+                    "little" => Endian::Little,
+                    "big" => Endian::Big,
+                    other => return Err(EdfError::InvalidArgument(format!("unknown byte order '{}'", other)))
+                });
+            },
+            "--threads-per-file" => {
+                options.threads_per_file = next_value(&mut args, &arg)?.parse()?;
+            },
+            "--missing-if" => {
+                options.missing_if.push(MissingCondition::parse(&next_value(&mut args, &arg)?)?);
+            },
+            "--no-write" => {
+                options.no_write = true;
+            },
+            "--rfc3339" => {
+                rfc3339 = true;
+            },
+            "--utc-offset" => {
+                let value: String = next_value(&mut args, &arg)?;
+                if value.len() != 6 || value.parse::<FixedOffset>().is_err() {
+                    return Err(EdfError::InvalidArgument(format!("can't parse '{}' as a UTC offset like +01:00", value)));
+                }
+                utc_offset = Some(value);
+            },
+            "--io-retries" => {
+                options.io_retries = next_value(&mut args, &arg)?.parse()?;
+            },
+            "--offscale-markers" => {
+                options.offscale_markers = true;
+            },
+            "--sort" => {
+                options.sort = match next_value(&mut args, &arg)?.as_str() {
+                    "none" => SortOrder::None,
+                    "path" => SortOrder::Path,
+                    "start-time" => SortOrder::StartTime,
+                    "size" => SortOrder::Size,
+                    other => return Err(EdfError::InvalidArgument(format!("unknown sort order '{}'", other)))
+                };
+            },
+            "--timeout" => {
+                let seconds: f64 = next_value(&mut args, &arg)?.parse()?;
+                match time::Duration::try_from_secs_f64(seconds) {
+                    Ok(timeout) if seconds > 0.0 => options.timeout = Some(timeout),
+                    _ => return Err(EdfError::InvalidArgument(format!("timeout must be a positive number of seconds, not {}", seconds)))
+                }
+            },
+            "--files-from" => {
+                options.files_from.push(PathBuf::from(next_value(&mut args, &arg)?));
+            },
+            _ if arg.starts_with("--") => {
+                return Err(EdfError::InvalidArgument(format!("unknown option '{}'", arg)));
+            },
+            _ => inputs.push(arg)
+        }
+    }
+    options.conversion = conversion.build();
+
+    if options.timeout.is_some() && options.merge_to.is_some() {
+        return Err(EdfError::InvalidArgument("--timeout can't be combined with --merge-to".to_string()));
+    }
+    if options.timeout.is_some() && (options.schema_only || options.split_signals || options.transpose || options.format == OutputFormat::Replay) {
+        return Err(EdfError::InvalidArgument("--timeout only works with the row-per-sample CSV, NPY and SQLite outputs".to_string()));
+    }
+    // EDF start times carry no zone, so RFC 3339 output only adds the
+    // designator: "Z" unless the recording's UTC offset is given.
+    if rfc3339 {
+        if options.conversion.datetime_format() != DEFAULT_DATETIME_FORMAT {
+            return Err(EdfError::InvalidArgument("--rfc3339 can't be combined with --datetime-format".to_string()));
+        }
+        options.conversion = conversion.datetime_format(&format!("{}{}", DEFAULT_DATETIME_FORMAT, utc_offset.as_deref().unwrap_or("Z"))).build();
+    } else if utc_offset.is_some() {
+        return Err(EdfError::InvalidArgument("--utc-offset needs --rfc3339".to_string()));
+    }
+    if options.split_signals && (options.merge_to.is_some() || options.verify || options.no_write || options.format != OutputFormat::Csv) {
+        return Err(EdfError::InvalidArgument("--split-signals can't be combined with --merge-to, --no-write, --format or verify".to_string()));
+    }
+    if options.transpose && (options.split_signals || options.merge_to.is_some() || options.verify || options.no_write || options.units_sidecar || options.format != OutputFormat::Csv) {
+        return Err(EdfError::InvalidArgument("--transpose can't be combined with --split-signals, --merge-to, --no-write, --units-sidecar, --format or verify".to_string()));
+    }
+    if options.sample_offset_column && (options.split_signals || options.transpose || !matches!(options.format, OutputFormat::Csv)) {
+        return Err(EdfError::InvalidArgument("--sample-offset-column only applies to the CSV with a row per sample".to_string()));
+    }
+    if options.sparse_timestamps && (options.split_signals || options.transpose || !matches!(options.format, OutputFormat::Csv)) {
+        return Err(EdfError::InvalidArgument("--sparse-timestamps only applies to the CSV with a row per sample".to_string()));
+    }
+    if options.on_duplicate_timestamp != DuplicateTimestamps::Keep && (options.split_signals || options.transpose || options.format == OutputFormat::Replay) {
+        return Err(EdfError::InvalidArgument("--on-duplicate-timestamp only applies to output with a row per sample of a shared timeline".to_string()));
+    }
+    if options.shift_dates.is_some() && !options.anonymize {
+        return Err(EdfError::InvalidArgument("--shift-dates only applies to anonymize".to_string()));
+    }
+    if !options.inline_metadata.is_empty() && (options.split_signals || options.transpose || options.format != OutputFormat::Csv) {
+        return Err(EdfError::InvalidArgument("--inline-metadata only applies to the CSV with a row per sample".to_string()));
+    }
+    if options.per_record_files && (options.gzip || options.durable || options.split_signals || options.transpose || options.merge_to.is_some() || options.verify || options.format != OutputFormat::Csv) {
+        return Err(EdfError::InvalidArgument("--per-record-files only applies to the plain CSV written per file".to_string()));
+    }
+    if options.durable && (options.gzip || options.split_signals || options.transpose || options.merge_to.is_some() || options.verify || options.format != OutputFormat::Csv) {
+        return Err(EdfError::InvalidArgument("--durable only applies to the plain CSV written per file".to_string()));
+    }
+    if options.record_stride > 1 && (options.split_signals || options.transpose || options.verify || options.skip_corrupt || options.format == OutputFormat::Replay) {
+        return Err(EdfError::InvalidArgument("--record-stride only applies to output with a row per sample, without verify or --skip-corrupt".to_string()));
+    }
+    if options.rate_header && options.format == OutputFormat::Replay {
+        return Err(EdfError::InvalidArgument("--rate-header doesn't apply to --format replay, which has one signal per row".to_string()));
+    }
+    if options.limit_rows.is_some() && (options.split_signals || options.transpose || options.verify || options.format == OutputFormat::Replay) {
+        return Err(EdfError::InvalidArgument("--limit-rows only applies to output with a row per sample, without verify".to_string()));
+    }
+    if compress_level_given && !options.gzip {
+        return Err(EdfError::InvalidArgument("--compress-level needs --gzip".to_string()));
+    }
+    if options.gzip && (options.split_signals || options.transpose || options.merge_to.is_some() || options.verify || options.format != OutputFormat::Csv) {
+        return Err(EdfError::InvalidArgument("--gzip can't be combined with --split-signals, --transpose, --merge-to, --format or verify".to_string()));
+    }
+    if options.units_sidecar && (options.merge_to.is_some() || options.format != OutputFormat::Csv) {
+        return Err(EdfError::InvalidArgument("--units-sidecar only applies to CSV output without --merge-to".to_string()));
+    }
+    if options.no_write && (options.merge_to.is_some() || options.verify || options.schema_only) {
+        return Err(EdfError::InvalidArgument("--no-write can't be combined with --merge-to, --schema-only or verify".to_string()));
+    }
+    if options.format != OutputFormat::Csv && (options.merge_to.is_some() || options.verify) {
+        return Err(EdfError::InvalidArgument("--format npy, sqlite or replay can't be combined with --merge-to or verify".to_string()));
+    }
+    if (options.format == OutputFormat::Sqlite) != options.db.is_some() {
+        return Err(EdfError::InvalidArgument("--format sqlite and --db go together".to_string()));
+    }
+    if let (Some(min_rate), Some(max_rate)) = (options.min_rate, options.max_rate) {
+        if min_rate > max_rate {
+            return Err(EdfError::InvalidArgument(format!("--min-rate {} is above --max-rate {}", min_rate, max_rate)));
+        }
+    }
+    // The CSV writer would quote every value containing the decimal
+    // separator, which tools that just split lines can't read.
+    if options.decimal == options.conversion.delimiter() as char {
+        return Err(EdfError::InvalidArgument(format!("--decimal and --delimiter can't both be '{}'", options.decimal)));
+    }
+    if b"\"\r\n".contains(&options.conversion.delimiter()) {
+        return Err(EdfError::InvalidArgument("the delimiter can't be a quote or a line break".to_string()));
+    }
+    if options.decimal != '.' && matches!(options.format, OutputFormat::Npy | OutputFormat::Sqlite) {
+        return Err(EdfError::InvalidArgument("--decimal only applies to CSV output".to_string()));
+    }
+    if options.standard_texts && !options.events_csv {
+        return Err(EdfError::InvalidArgument("--standard-texts needs --events-csv".to_string()));
+    }
+    // The reference's labels go through the same label options as the files
+    // they are matched against, so they can only be read once all are known.
+    if let Some(reference) = channels_from {
+        if options.columns_order.is_some() {
+            return Err(EdfError::InvalidArgument("--channels-from-edf can't be combined with --columns-order".to_string()));
+        }
+        options.columns_order = Some(read_reference_labels(&reference, &options)?);
+        options.drop_unlisted = true;
+    }
+    if options.drop_unlisted && options.columns_order.is_none() {
+        return Err(EdfError::InvalidArgument("--drop-unlisted needs --columns-order".to_string()));
+    }
+    if options.verify && (options.merge_to.is_some() || options.schema_only) {
+        return Err(EdfError::InvalidArgument("verify can't be combined with --merge-to or --schema-only".to_string()));
+    }
+    if options.fixed_point.is_some() && (options.precision.is_some() || options.conversion.raw_digital()) {
+        return Err(EdfError::InvalidArgument("--fixed-point can't be combined with --precision or --raw".to_string()));
+    }
+    // `--raw` overrides the clinical profile's µV, but not an explicit unit.
+    if options.target_unit.is_some() && options.conversion.raw_digital() && !target_unit_given {
+        options.target_unit = None;
+    }
+    if options.target_unit.is_some() && options.conversion.raw_digital() {
+        return Err(EdfError::InvalidArgument("--target-unit only applies to scaled values".to_string()));
+    }
+    Ok((options, inputs))
+}
+
+
+fn main() {
+    let (options, mut inputs): (Options, Vec<String>) = match parse_args(env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+    let options: Arc<Options> = Arc::new(options);
+
+    for list_path in &options.files_from {
+        match read_file_list(list_path) {
+            Ok(listed) => inputs.extend(listed),
+            Err(e) => {
+                eprintln!("{}: {}", list_path.to_string_lossy(), e);
+                process::exit(2);
+            }
+        }
+    }
+
+    if options.selftest {
+        process::exit(if selftest() { 0 } else { 1 });
+    }
+
+    if options.to_edf {
+        let target_dir: &Path = Path::new("./edf_to_csv_files/");
+        if let Err(e) = prepare_output_dir(target_dir) {
+            eprintln!("{}: the output directory isn't writable: {}", target_dir.to_string_lossy(), e);
+            process::exit(2);
+        }
+        let mut status_logger: Writer<File> = get_status_logger();
+        let mut any_failed: bool = false;
+        for csv_path in inputs.iter().map(PathBuf::from) {
+            match csv_to_edf(&csv_path, target_dir, &options) {
+                Ok(()) => log_status(&mut status_logger, &csv_path, "CSV converted to EDF!"),
+                Err(e) => {
+                    any_failed = true;
+                    if options.json_errors {
+                        eprintln!("{}", serde_json::to_string(&ErrorReport::new(&csv_path, &e)).unwrap());
+                    }
+                    log_status(&mut status_logger, &csv_path, &e.to_string())
+                }
+            }
+        }
+        status_logger.flush().unwrap();
+        process::exit(if any_failed { 1 } else { 0 });
+    }
+
+    let mut edf_file_paths: Vec<PathBuf> = Vec::new();
+    for arg in &inputs {
+        let file_path: PathBuf = PathBuf::from(arg);
+        if arg == STDIN_PATH || is_url(&file_path) || is_edf_file(&file_path) {
+            edf_file_paths.push(file_path)
+        } else if is_zip_file(&file_path) {
+            match list_zip_edf_files(&file_path) {
+                Ok(entries) => edf_file_paths.extend(entries),
+                Err(e) => {
+                    eprintln!("{}: {}", file_path.to_string_lossy(), e);
+                    process::exit(2);
+                }
+            }
+        } else if file_path.is_dir() {
+            edf_file_paths.extend(list_edf_files(&file_path, &options))
+        }
+    }
+
+    // Finding nothing to convert is most likely a wrong path, so it gets its
+    // own exit code instead of silently succeeding.
+    if edf_file_paths.is_empty() {
+        if inputs.is_empty() {
+            eprintln!("no input files or directories given");
+        } else {
+            eprintln!("no EDF files found under {}", inputs.join(", "));
+        }
+        process::exit(3);
+    }
+    if !options.include_globs.is_empty() || !options.exclude_globs.is_empty() {
+        edf_file_paths.retain(|file_path| selected_by_globs(file_path, &options));
+        if edf_file_paths.is_empty() {
+            eprintln!("no EDF files under {} match --include-glob/--exclude-glob", inputs.join(", "));
+            process::exit(3);
+        }
+    }
+    if let Some(since) = options.since {
+        edf_file_paths.retain(|file_path| modified_since(file_path, since));
+        // Unlike finding no files at all, this is the normal outcome of an
+        // incremental run when nothing changed.
+        if edf_file_paths.is_empty() {
+            eprintln!("no EDF files under {} were modified since {}", inputs.join(", "), since.format("%Y-%m-%dT%H:%M:%S%:z"));
+            process::exit(0);
+        }
+    }
+    sort_files(&mut edf_file_paths, &options);
+
+    if options.list {
+        let mut any_failed: bool = false;
+        for file_path in &edf_file_paths {
+            if let Err(e) = list_edf(file_path, &options) {
+                any_failed = true;
+                if options.json_errors {
+                    eprintln!("{}", serde_json::to_string(&ErrorReport::new(file_path, &e)).unwrap());
+                } else {
+                    eprintln!("{}: {}", file_path.to_string_lossy(), e);
+                }
+            }
+        }
+        process::exit(if any_failed { 1 } else { 0 });
+    }
+
+    let target_dir: &Path = Path::new("./edf_to_csv_files/");
+    if options.dry_run_list {
+        let planned: Vec<(&PathBuf, PathBuf)> = edf_file_paths
+            .iter()
+            .flat_map(|file_path| planned_outputs(file_path, target_dir, &options).into_iter().map(move |output| (file_path, output)))
+            .collect();
+        let mut counts: HashMap<&PathBuf, usize> = HashMap::new();
+        for (_, output) in &planned {
+            *counts.entry(output).or_default() += 1;
+        }
+        let mut any_collided: bool = false;
+        for (file_path, output) in &planned {
+            // A merge target or SQLite database is shared on purpose.
+            let shared: bool = options.merge_to.is_some() || options.format == OutputFormat::Sqlite;
+            if !shared && counts[output] > 1 {
+                any_collided = true;
+                println!("{} -> {} (collides with another input)", file_path.to_string_lossy(), output.to_string_lossy());
+            } else {
+                println!("{} -> {}", file_path.to_string_lossy(), output.to_string_lossy());
+            }
+        }
+        process::exit(if any_collided { 1 } else { 0 });
+    }
+    if !options.verify && !options.no_write {
+        if let Err(e) = prepare_output_dir(target_dir) {
+            eprintln!("{}: the output directory isn't writable: {}", target_dir.to_string_lossy(), e);
+            process::exit(2);
+        }
+    }
+
+    if options.anonymize {
+        let mut status_logger: Writer<File> = get_status_logger();
+        let mut any_failed: bool = false;
+        for file_path in &edf_file_paths {
+            match anonymize_edf(file_path, target_dir, &options) {
+                Ok(()) => log_status(&mut status_logger, file_path, "File anonymized!"),
+                Err(e) => {
+                    any_failed = true;
+                    if options.json_errors {
+                        eprintln!("{}", serde_json::to_string(&ErrorReport::new(file_path, &e)).unwrap());
+                    }
+                    log_status(&mut status_logger, file_path, &e.to_string())
+                }
+            }
+        }
+        status_logger.flush().unwrap();
+        process::exit(if any_failed { 1 } else { 0 });
+    }
+
+    let mut merge: Option<MergeTarget> = match &options.merge_to {
+        Some(merge_path) => match csv_writer(merge_path, &options) {
+            Ok(writer) => Some(MergeTarget { writer, columns: None }),
+            Err(e) => {
+                eprintln!("{}: {}", merge_path.to_string_lossy(), EdfError::from(e));
+                process::exit(2);
+            }
+        },
+        None => None
+    };
+
+    let mut status_logger: Writer<File> = get_status_logger();
+    let mut any_failed: bool = false;
+    if options.gzip {
+        eprintln!("writing gzip-compressed CSV at level {}", options.compress_level);
+    }
+
+    for file_path in edf_file_paths {
+        let started: Instant = Instant::now();
+        let result: Result<usize, EdfError> = if options.schema_only {
+            write_schema(&file_path, target_dir, &options).map(|()| 0)
+        } else if options.split_signals {
+            split_edf(&file_path, target_dir, &options)
+        } else if options.transpose {
+            transpose_edf(&file_path, target_dir, &options)
+        } else if options.format == OutputFormat::Replay && !options.no_write {
+            replay_edf(&file_path, target_dir, &options)
+        } else if let Some(timeout) = options.timeout {
+            parse_edf_with_timeout(&file_path, target_dir, &options, timeout)
+        } else {
+            parse_edf(&file_path, target_dir, &options, merge.as_mut(), None)
+        };
+        match result {
+            Ok(_) if options.verify => {
+                println!("{}: OK", file_path.to_string_lossy());
+                log_status(&mut status_logger, &file_path, "CSV matches the EDF file!")
+            },
+            Ok(num_samples) if options.verbose => {
+                let seconds: f64 = started.elapsed().as_secs_f64();
+                let timing: String = format!("{} samples in {:.3} s ({:.0} samples/s)", num_samples, seconds, num_samples as f64 / seconds);
+                // `--no-write` already prints its own throughput line.
+                if !options.no_write {
+                    println!("{}: {}", file_path.to_string_lossy(), timing);
+                }
+                let mut message: String = format!("File parsed successfully! {}", timing);
+                if options.gzip {
+                    message.push_str(&format!(", gzip level {}", options.compress_level));
+                }
+                log_status(&mut status_logger, &file_path, &message)
+            },
+            Ok(_) if options.gzip => {
+                let message: String = format!("File parsed successfully! gzip level {}", options.compress_level);
+                log_status(&mut status_logger, &file_path, &message)
+            },
+            Ok(_) => log_status(&mut status_logger, &file_path, "File parsed successfully!"),
+            Err(e) => {
+                any_failed = true;
+                if options.verify {
+                    println!("{}: {}", file_path.to_string_lossy(), e);
+                }
+                if options.json_errors {
+                    eprintln!("{}", serde_json::to_string(&ErrorReport::new(&file_path, &e)).unwrap());
+                }
+                log_status(&mut status_logger, &file_path, &e.to_string())
+            }
+        }
+    }
+    status_logger.flush().unwrap();
+
+    if any_failed {
+        process::exit(1);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::AtomicUsize;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    // Tracks the bytes allocated at any time, and the most ever at once,
+    // across all threads.
+    struct CountingAllocator;
+    static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+    static PEAK: AtomicUsize = AtomicUsize::new(0);
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let allocated: usize = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+            System.dealloc(ptr, layout)
+        }
+    }
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir: PathBuf = env::temp_dir().join(format!("edf_to_csv_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // An 8 MiB EDF inside a ZIP archive: 4 signals of 256 samples per record.
+    fn write_large_zip(dir: &Path) -> PathBuf {
+        let signal = |label: &str| Signal {
+            label: label.to_string(),
+            dimension: "uV".to_string(),
+            bounds: Bounds {digital_min: -32768.0, digital_max: 32767.0, physical_min: -500.0, physical_max: 500.0},
+            num_samples: 256
+        };
+        let header: EdfHeader = EdfHeader {
+            start: NaiveDate::from_ymd_opt(2023, 1, 15).unwrap().and_hms_opt(8, 30, 0).unwrap(),
+            patient_info: None,
+            recording_info: None,
+            num_records: 4096,
+            record_duration: 1.0,
+            signals: vec![signal("A"), signal("B"), signal("C"), signal("D")],
+            data_offset: 256 * 5,
+            warnings: Vec::new()
+        };
+        let zip_path: PathBuf = dir.join("large.zip");
+        let mut archive: ZipWriter<File> = ZipWriter::new(File::create(&zip_path).unwrap());
+        archive.start_file("large.edf", SimpleFileOptions::default()).unwrap();
+        write_header(&mut archive, &header).unwrap();
+        let values: Vec<i16> = (0..1024).map(|i| (i * 37 % 2000 - 1000) as i16).collect();
+        for _ in 0..header.num_records {
+            write_record_samples(&mut archive, &values).unwrap();
+        }
+        archive.finish().unwrap();
+        zip_path.join("large.edf")
+    }
+
+    #[test]
+    fn low_memory_conversion_stays_below_the_input_size() {
+        let dir: PathBuf = temp_dir("low_memory");
+        let edf_path: PathBuf = write_large_zip(&dir);
+        let options: Options = Options { low_memory: true, no_write: true, ..Options::default() };
+
+        let before: usize = ALLOCATED.load(Ordering::Relaxed);
+        PEAK.store(before, Ordering::Relaxed);
+        let num_samples: usize = parse_edf(&edf_path, &dir, &options, None, None).unwrap();
+        let peak: usize = PEAK.load(Ordering::Relaxed) - before;
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(num_samples, 4096 * 1024);
+        // The entry holds 8 MiB of samples; the chunks in flight, the reader's
+        // buffer and one record take well under 1 MiB.
+        assert!(peak < 1024 * 1024, "peak allocation was {} bytes", peak);
+    }
+
+    #[test]
+    fn sanitize_file_labels_replaces_path_separators() {
+        assert_eq!(sanitize_file_labels(&["EEG Fp1/A2", "..\\ECG", "C3:M2"]), ["EEG_Fp1_A2", "___ECG", "C3_M2"]);
+    }
+
+    #[test]
+    fn sanitize_file_labels_avoids_reserved_names() {
+        assert_eq!(sanitize_file_labels(&["CON", "nul", "Com1", "LPT9", "CONSOLE"]), ["CON_", "nul_", "Com1_", "LPT9_", "CONSOLE"]);
+    }
+
+    #[test]
+    fn sanitize_file_labels_numbers_labels_that_collide() {
+        assert_eq!(sanitize_file_labels(&["EEG Fp1", "EEG-Fp1", "EEG_Fp1", "eeg fp1"]), ["EEG_Fp1", "EEG-Fp1", "EEG_Fp1_2", "eeg_fp1_3"]);
+        assert_eq!(sanitize_file_labels(&["", "  ", "signal"]), ["signal", "signal_2", "signal_3"]);
+    }
+
+    #[test]
+    fn collect_edf_files_skips_a_missing_directory() {
+        let dir: PathBuf = temp_dir("collect");
+        File::create(dir.join("a.edf")).unwrap();
+        let mut visited: Vec<PathBuf> = Vec::new();
+        assert!(collect_edf_files(&dir.join("gone"), &Options::default(), &mut visited).is_empty());
+        assert_eq!(collect_edf_files(&dir, &Options::default(), &mut visited), [dir.join("a.edf")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn clinical_profile_converts_to_microvolts_unless_raw() {
+        let (options, _) = parse_args(["a.edf", "--profile", "clinical"].map(String::from).into_iter()).unwrap();
+        assert_eq!(options.target_unit.as_deref(), Some("uV"));
+        let (options, _) = parse_args(["a.edf", "--profile", "clinical", "--raw"].map(String::from).into_iter()).unwrap();
+        assert_eq!(options.target_unit, None);
+        assert!(parse_args(["a.edf", "--target-unit", "mV", "--raw"].map(String::from).into_iter()).is_err());
+    }
+
+    fn gap_filler(max_gap: usize) -> GapFiller {
+        let options: Options = Options {interpolation: Interpolation::Linear, max_gap, ..Options::default()};
+        GapFiller::new(1, &options)
+    }
+
+    fn filled(records: Vec<PendingRecord>) -> Vec<Vec<Option<f64>>> {
+        records.into_iter().map(|(_, _, mut scaled)| scaled.remove(0)).collect()
+    }
+
+    #[test]
+    fn gap_filler_fills_gaps_across_records() {
+        let mut filler: GapFiller = gap_filler(3);
+        assert_eq!(filled(filler.push(0, Vec::new(), vec![vec![Some(0.0), Some(1.0), None]])), Vec::<Vec<Option<f64>>>::new());
+        assert_eq!(filled(filler.push(1, Vec::new(), vec![vec![None, None, Some(5.0)]])), [
+            vec![Some(0.0), Some(1.0), Some(2.0)],
+            vec![Some(3.0), Some(4.0), Some(5.0)]
+        ]);
+    }
+
+    #[test]
+    fn gap_filler_holds_back_no_more_than_max_gap() {
+        let mut filler: GapFiller = gap_filler(2);
+        assert!(filler.push(0, Vec::new(), vec![vec![Some(0.0), None]]).is_empty());
+        assert!(filler.push(1, Vec::new(), vec![vec![None]]).is_empty());
+        // A third missing sample makes the gap too long to fill.
+        assert_eq!(filled(filler.push(2, Vec::new(), vec![vec![None, Some(4.0)]])), [vec![Some(0.0), None], vec![None], vec![None, Some(4.0)]]);
+    }
+
+    #[test]
+    fn gap_filler_leaves_open_gaps_and_skipped_records_alone() {
+        let mut filler: GapFiller = gap_filler(5);
+        assert!(filler.push(0, Vec::new(), vec![vec![Some(0.0), None]]).is_empty());
+        // Record 1 was skipped, so record 0's gap has no right neighbour.
+        assert_eq!(filled(filler.push(2, Vec::new(), vec![vec![None, Some(3.0), None]])), [vec![Some(0.0), None]]);
+        assert_eq!(filled(filler.finish()), [vec![None, Some(3.0), None]]);
+    }
+
+    #[test]
+    fn datetime_formats_that_cannot_format_a_naive_datetime_are_rejected() {
+        for format in ["%Y %z", "%Y %Z", "%Q"] {
+            let args = ["a.edf", "--datetime-format", format].map(String::from);
+            assert!(matches!(parse_args(args.into_iter()), Err(EdfError::InvalidArgument(_))), "{} was accepted", format);
+        }
+        let args = ["a.edf", "--datetime-format", "%d.%m.%Y %H:%M"].map(String::from);
+        assert!(parse_args(args.into_iter()).is_ok());
+    }
+}